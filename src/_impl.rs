@@ -1,19 +1,30 @@
 use std::{
-    ffi::OsStr,
+    collections::HashSet,
+    ffi::{OsStr, OsString},
     fs::File,
-    io::{ErrorKind, Result},
-    path::Path,
+    io::{self as std_io, ErrorKind},
+    marker::PhantomData,
+    path::{Path, PathBuf},
 };
 
 #[cfg(windows)]
-use fs_at::os::windows::{FileExt, OpenOptionsExt};
-#[cfg(feature = "parallel")]
-use rayon::prelude::*;
-#[cfg(windows)]
-use windows_sys::Win32::Storage::FileSystem::{DELETE, FILE_LIST_DIRECTORY, FILE_READ_ATTRIBUTES};
+use fs_at::os::windows::FileExt;
+use normpath::PathExt;
 
+mod dir_stack;
 mod io;
+#[cfg(not(windows))]
+mod mounts;
+mod outcome;
+#[cfg(any(feature = "parallel", feature = "threads"))]
+mod parallel;
 mod path_components;
+mod prescan;
+#[cfg(not(windows))]
+pub(crate) mod resolve;
+pub(crate) mod steps;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
 
 cfg_if::cfg_if! {
     if #[cfg(windows)] {
@@ -25,194 +36,1450 @@ cfg_if::cfg_if! {
     }
 }
 
-pub(crate) fn default_parallel_mode() -> ParallelMode {
-    #[cfg(all(feature = "parallel", not(target_os = "macos")))]
-    return ParallelMode::Parallel;
-    #[cfg(any(not(feature = "parallel"), target_os = "macos"))]
-    return ParallelMode::Serial;
+impl Remover {
+    /// `path`, normalised to be opened as the root of a removal. Paths to be
+    /// resolved a name at a time are left as given, as normalising would
+    /// follow symlinks along them.
+    pub(crate) fn normalize_root(&self, path: &Path) -> Result<PathBuf, RemoveError> {
+        #[cfg(not(windows))]
+        if !self.resolution.normalizes() {
+            return Ok(path.to_path_buf());
+        }
+        path.normalize()
+            .map(|path| path.into_path_buf())
+            .map_err(|e| root_error::<OsIo>(Operation::Open, path, e))
+    }
+
+    /// The parallel mode to remove with; serial unless chosen, whether on the
+    /// builder or by [`Remover::for_filesystem`].
+    fn parallel_mode(&self) -> ParallelMode {
+        self.parallel.unwrap_or(ParallelMode::Serial)
+    }
+
+    /// This remover, with the settings the builder left open decided by the
+    /// policy for the filesystem holding `d`.
+    fn for_filesystem<I: io::Io>(&self, d: &File) -> Remover {
+        let filesystem = I::filesystem(d)
+            .inspect_err(|_e| {
+                #[cfg(feature = "log")]
+                log::debug!("could not determine the filesystem: {}", _e);
+            })
+            .unwrap_or(Filesystem::Other);
+        let policy = self
+            .filesystems
+            .iter()
+            .find(|(fs, _)| *fs == filesystem)
+            .map_or_else(
+                || FilesystemPolicy::for_filesystem(filesystem),
+                |(_, policy)| *policy,
+            );
+        #[cfg(feature = "log")]
+        log::debug!("removing from {:?}: {:?}", filesystem, policy);
+        let parallel = match policy.parallel {
+            #[cfg(feature = "parallel")]
+            true => ParallelMode::Parallel,
+            _ => ParallelMode::Serial,
+        };
+        Remover {
+            parallel: Some(self.parallel.unwrap_or(parallel)),
+            inode_order: self.inode_order.or(policy.inode_order),
+            scan_batch: Some(policy.scan_batch),
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(not(windows))]
+impl<T: std::os::fd::AsFd> super::RemoveDirAt for T {
+    fn remove_dir_all_at_with<N: AsRef<OsStr>>(
+        &self,
+        remover: &Remover,
+        name: N,
+        expected: Option<EntryId>,
+    ) -> Result<RemovalReport, RemoveError> {
+        let parent = File::from(
+            self.as_fd()
+                .try_clone_to_owned()
+                .map_err(|e| root_error::<OsIo>(Operation::Open, Path::new(name.as_ref()), e))?,
+        );
+        let expected = expected.map(|id| (id.dev(), id.ino()));
+        remove_dir_all_at::<OsIo>(parent, name.as_ref(), expected.as_ref(), remover)
+    }
 }
 
 impl super::RemoveDir for std::fs::File {
-    fn remove_dir_contents(&mut self, debug_root: Option<&Path>) -> Result<()> {
+    fn remove_dir_contents(&mut self, debug_root: Option<&Path>) -> std_io::Result<()> {
+        let remover = RemoverBuilder::new().build();
+        _remove_dir_contents::<OsIo>(self, debug_root.unwrap_or(Path::new("")), &remover)
+            .map(drop)
+            .map_err(RemoveError::into_io_error)
+    }
+
+    fn remove_dir_contents_with(
+        &mut self,
+        remover: &Remover,
+        debug_root: Option<&Path>,
+    ) -> std_io::Result<()> {
         // thunk over to the free version adding in the os-specific IO trait impl
-        let debug_root = match debug_root {
-            None => PathComponents::Path(Path::new("")),
-            Some(debug_root) => PathComponents::Path(debug_root),
-        };
-        _remove_dir_contents::<OsIo>(self, &debug_root)
+        _remove_dir_contents::<OsIo>(self, debug_root.unwrap_or(Path::new("")), remover)
+            .map(drop)
+            .map_err(RemoveError::into_io_error)
     }
 }
 
 /// Entry point for deprecated function
-pub(crate) fn _ensure_empty_dir_path<I: io::Io, P: AsRef<Path>>(path: P) -> Result<()> {
+pub(crate) fn _ensure_empty_dir_path<I: io::Io, P: AsRef<Path>>(
+    path: P,
+    remover: &Remover,
+) -> Result<RemovalReport, RemoveError> {
     // This is as TOCTOU safe as we can make it. Attacks via link replacements
     // in interior components of the path is still possible. if the create
     // succeeds, mission accomplished. if the create fails, open the dir
     // (subject to races again), and then proceed to delete the contents via the
     // descriptor.
+    #[cfg(not(windows))]
+    if let Some(parent) = remover.resolution.open_parent::<I>(path.as_ref()) {
+        // Resolved a name at a time, there are no such races: the parent is
+        // held
+        let path = path.as_ref();
+        let (parent, name) = parent?;
+        return match fs_at::OpenOptions::default().mkdir_at(&parent, name) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let mut existing_dir = I::open_dir_at(&parent, name)
+                    .map_err(|e| root_error::<I>(Operation::Open, path, e))?;
+                _remove_dir_contents::<I>(&mut existing_dir, path, remover)
+            }
+            Err(e) => Err(root_error::<I>(Operation::CreateDir, path, e)),
+            Ok(_) => Outcome::new(remover, path).finish(Ok(())),
+        };
+    }
     match std::fs::create_dir(&path) {
         Err(e) if e.kind() == ErrorKind::AlreadyExists => {
             // Exists and is a dir. Open it
-            let mut existing_dir = I::open_dir(path.as_ref())?;
-            existing_dir.remove_dir_contents(Some(path.as_ref()))
+            let mut existing_dir = I::open_dir(path.as_ref())
+                .map_err(|e| root_error::<I>(Operation::Open, path.as_ref(), e))?;
+            _remove_dir_contents::<I>(&mut existing_dir, path.as_ref(), remover)
         }
-        otherwise => otherwise,
+        Err(e) => Err(root_error::<I>(Operation::CreateDir, path.as_ref(), e)),
+        Ok(()) => Outcome::new(remover, path.as_ref()).finish(Ok(())),
     }
 }
 
 // Deprecated entry point
-pub(crate) fn _remove_dir_contents_path<I: io::Io, P: AsRef<Path>>(path: P) -> Result<()> {
-    let mut d = I::open_dir(path.as_ref())?;
-    _remove_dir_contents::<I>(&mut d, &PathComponents::Path(path.as_ref()))
+pub(crate) fn _remove_dir_contents_path<I: io::Io, P: AsRef<Path>>(
+    path: P,
+    remover: &Remover,
+) -> Result<RemovalReport, RemoveError> {
+    let mut d = open_dir::<I>(path.as_ref(), remover)?;
+    _remove_dir_contents::<I>(&mut d, path.as_ref(), remover)
+}
+
+/// Open the directory at `path`, resolved as `remover` resolves paths.
+fn open_dir<I: io::Io>(path: &Path, remover: &Remover) -> Result<File, RemoveError> {
+    #[cfg(not(windows))]
+    if let Some(result) = remover.resolution.open_dir::<I>(path) {
+        return result;
+    }
+    let _ = remover;
+    I::open_dir(path).map_err(|e| root_error::<I>(Operation::Open, path, e))
 }
 
 /// exterior lifetime interface to dir removal
-fn _remove_dir_contents<I: io::Io>(d: &mut File, debug_root: &PathComponents<'_>) -> Result<()> {
-    let owned_handle = I::duplicate_fd(d)?;
-    remove_dir_contents_recursive::<I>(owned_handle, debug_root, default_parallel_mode())?;
-    Ok(())
+fn _remove_dir_contents<I: io::Io>(
+    d: &mut File,
+    debug_root: &Path,
+    remover: &Remover,
+) -> Result<RemovalReport, RemoveError> {
+    let owned_handle =
+        I::duplicate_fd(d).map_err(|e| root_error::<I>(Operation::Open, debug_root, e))?;
+    let mut outcome = Outcome::new(remover, debug_root);
+    outcome
+        .one_file_system(&owned_handle, remover)
+        .map_err(|e| root_error::<I>(Operation::Stat, debug_root, e))?;
+    outcome.prescan::<I>(&owned_handle, None, remover);
+    let result = remove_dir_contents_with::<I>(owned_handle, debug_root, remover, &outcome);
+    outcome.finish(result.map(drop))
 }
 
 /// deprecated interface
 pub(crate) fn remove_dir_all_path<I: io::Io, P: AsRef<Path>>(
     path: P,
-    parallel: ParallelMode,
-) -> Result<()> {
-    let p = path.as_ref();
-    // Opportunity 1 for races
-    let d = I::open_dir(p)?;
-    let debug_root = PathComponents::Path(if p.has_root() { p } else { Path::new(".") });
-    remove_dir_contents_recursive::<OsIo>(d, &debug_root, parallel)?;
-    // Opportunity 2 for races
-    std::fs::remove_dir(&path)?;
-    #[cfg(feature = "log")]
-    log::trace!("removed {}", &debug_root);
+    remover: &Remover,
+) -> Result<RemovalReport, RemoveError> {
+    let debug_root = path.as_ref();
+    let (d, root) = RootEntry::<I>::open(debug_root, remover)?;
+    remove_opened_dir_all::<I>(d, root, debug_root, remover)
+}
+
+/// Remove the directory `name` of `parent`, which must be a single component
+/// of a path, and its contents, if it is the directory `expected`.
+#[cfg(not(windows))]
+pub(crate) fn remove_dir_all_at<I: io::Io>(
+    parent: File,
+    name: &OsStr,
+    expected: Option<&I::UniqueIdentifier>,
+    remover: &Remover,
+) -> Result<RemovalReport, RemoveError> {
+    let debug_root = Path::new(name);
+    let mut components = debug_root.components();
+    let single = match (components.next(), components.next()) {
+        (Some(std::path::Component::Normal(component)), None) => component == name,
+        _ => false,
+    };
+    if !single {
+        return Err(RemoveError::new(
+            name.into(),
+            Operation::Open,
+            RemoveErrorKind::Other,
+            std_io::Error::new(ErrorKind::InvalidInput, "not the name of a directory entry"),
+        ));
+    }
+    let (d, root) = RootEntry::<I>::open_at(parent, name, debug_root, expected)?;
+    remove_opened_dir_all::<I>(d, root, debug_root, remover)
+}
+
+/// List the entries under `path`, relative to it, after the metadata of the
+/// directory itself, for a [`crate::DeletionPlan`].
+pub(crate) fn plan_path<I: io::Io>(
+    path: &Path,
+    remover: &Remover,
+) -> Result<PlannedTree, RemoveError> {
+    let d = open_dir::<I>(path, remover)?;
+    let root = I::metadata(&d).map_err(|e| root_error::<I>(Operation::Stat, path, e))?;
+    let mut outcome = Outcome::new(remover, path);
+    outcome
+        .one_file_system(&d, remover)
+        .map_err(|e| root_error::<I>(Operation::Stat, path, e))?;
+    let mut entries = Vec::new();
+    prescan::walk::<I>(&d, path, remover, &outcome, |entry, metadata| {
+        let entry = entry.strip_prefix(path).expect("walked from the root");
+        entries.push((entry.to_path_buf(), metadata));
+    })?;
+    Ok((root, entries))
+}
+
+/// The metadata of a directory, and the paths and metadata of the entries
+/// under it.
+pub(crate) type PlannedTree = (EntryMetadata, Vec<(PathBuf, EntryMetadata)>);
+
+/// Remove the tree at `path`, as [`remove_dir_all_path`] does, if the
+/// directory there is still the one `expected`.
+pub(crate) fn remove_planned<I: io::Io>(
+    path: &Path,
+    expected: &EntryMetadata,
+    remover: &Remover,
+) -> Result<RemovalReport, RemoveError> {
+    let (d, root) = RootEntry::<I>::open(path, remover)?;
+    let metadata = I::metadata(&d).map_err(|e| root_error::<I>(Operation::Stat, path, e))?;
+    if !metadata.is_same_entry(expected) {
+        return Err(RemoveError::new(
+            path.into(),
+            Operation::Open,
+            RemoveErrorKind::Replaced,
+            std_io::Error::other("replaced since the removal was planned"),
+        ));
+    }
+    remove_opened_dir_all::<I>(d, root, path, remover)
+}
+
+/// Remove the directory `d`, opened at `debug_root` as `root`, and its
+/// contents.
+fn remove_opened_dir_all<I: io::Io>(
+    d: File,
+    root: RootEntry<I>,
+    debug_root: &Path,
+    remover: &Remover,
+) -> Result<RemovalReport, RemoveError> {
+    let mut outcome = Outcome::new(remover, debug_root);
+    // Read before emptying, while the directory is still the one opened
+    let usage = read_metadata(&outcome, || I::metadata(&d))
+        .map_err(|e| root_error::<I>(Operation::Stat, debug_root, e))?;
+    outcome
+        .one_file_system(&d, remover)
+        .map_err(|e| root_error::<I>(Operation::Stat, debug_root, e))?;
+    outcome.prescan::<I>(&d, usage, remover);
+    let result = remove_dir_and_contents::<I>(d, root, debug_root, usage, remover, &outcome);
+    outcome.finish(result)
+}
+
+fn remove_dir_and_contents<I: io::Io>(
+    d: File,
+    root: RootEntry<I>,
+    debug_root: &Path,
+    usage: Option<EntryMetadata>,
+    remover: &Remover,
+    outcome: &Outcome,
+) -> Result<(), RemoveError> {
+    if remove_dir_contents_with::<I>(d, debug_root, remover, outcome)? {
+        let path = PathComponents::new(debug_root, std::iter::empty());
+        outcome.checkpoint(&path, Operation::Rmdir)?;
+        let timer = outcome.timer(Phase::Rmdir);
+        let result = root.remove(debug_root);
+        drop(timer);
+        match result {
+            Ok(()) => {
+                #[cfg(feature = "log")]
+                log::trace!("removed {}", debug_root.display());
+                usage.into_iter().for_each(|usage| outcome.removed(usage));
+            }
+            Err(e) => outcome.absorb(e)?,
+        }
+    } else {
+        outcome.leave(debug_root.to_path_buf());
+    }
     Ok(())
 }
 
-use crate::{ParallelMode, RemoveDir};
+/// Where the root of a removal was opened, to remove it from once emptied.
+pub(crate) struct RootEntry<I: io::Io> {
+    /// The parent, opened without following a symlink there, the root's name
+    /// in it, and the identity of the directory opened by that name. `None`
+    /// where the root is removed by path instead.
+    #[cfg(not(windows))]
+    at: Option<(File, OsString, I::UniqueIdentifier)>,
+    _io: PhantomData<I>,
+}
 
-use self::path_components::PathComponents;
+impl<I: io::Io> RootEntry<I> {
+    /// Open the directory at `path`. On Unix it is opened by name from its
+    /// parent, so that the directory removed at the end can be checked to be
+    /// the one opened: otherwise the path could be pointed at another
+    /// directory in the meantime, which would be removed if empty. If
+    /// `remover` resolves paths a name at a time, so is the parent.
+    pub(crate) fn open(path: &Path, remover: &Remover) -> Result<(File, Self), RemoveError> {
+        let fail = |operation, e| root_error::<I>(operation, path, e);
+        #[cfg(not(windows))]
+        if let Some(parent) = remover.resolution.open_parent::<I>(path) {
+            let (parent, name) = parent?;
+            return Self::open_at(parent, name, path, None);
+        }
+        #[cfg(windows)]
+        let _ = remover;
+        #[cfg(not(windows))]
+        if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            match I::open_dir(parent) {
+                Ok(parent) => return Self::open_at(parent, name, path, None),
+                // Searching a directory doesn't need permission to read it,
+                // so its entries may still be removable
+                Err(e) if I::error_kind(&e) == RemoveErrorKind::PermissionDenied => (),
+                Err(e) => return Err(fail(Operation::Open, e)),
+            }
+        }
+        let d = I::open_dir(path).map_err(|e| fail(Operation::Open, e))?;
+        let root = Self {
+            #[cfg(not(windows))]
+            at: None,
+            _io: PhantomData,
+        };
+        Ok((d, root))
+    }
 
-// Core workhorse, heading towards this being able to be tasks.
-fn remove_dir_contents_recursive<I: io::Io>(
-    mut d: File,
-    debug_root: &PathComponents<'_>,
-    parallel: ParallelMode,
-) -> Result<File> {
-    #[cfg(feature = "log")]
-    log::trace!("scanning {}", &debug_root);
-    // We take a os level clone of the FD so that there are no rust-level
-    // lifetime concerns. It would *not* be ok to do readdir on one file twice
-    // (even via the cloned FD) concurrently because of shared kernel state: the
-    // readdir state is stored per file, not per FD.
-    let dirfd = I::duplicate_fd(&mut d)?;
-    match parallel {
-        ParallelMode::Serial => {
-            let mut iter = fs_at::read_dir(&mut d)?;
-            iter.try_for_each(|dir_entry| {
-                scan_and_remove_entry_recursively::<I>(debug_root, &dirfd, dir_entry, parallel)
-            })?;
+    /// Open the directory `name` of `parent`, at `path`, failing with
+    /// [`RemoveErrorKind::Replaced`] if it isn't the one `expected`.
+    #[cfg(not(windows))]
+    pub(crate) fn open_at(
+        parent: File,
+        name: &OsStr,
+        path: &Path,
+        expected: Option<&I::UniqueIdentifier>,
+    ) -> Result<(File, Self), RemoveError> {
+        let fail = |operation, e| root_error::<I>(operation, path, e);
+        let d = I::open_dir_at(&parent, name).map_err(|e| fail(Operation::Open, e))?;
+        let identifier = I::unique_identifier(&d).map_err(|e| fail(Operation::Stat, e))?;
+        if expected.is_some_and(|expected| *expected != identifier) {
+            return Err(RemoveError::new(
+                path.into(),
+                Operation::Open,
+                RemoveErrorKind::Replaced,
+                std_io::Error::other("not the directory expected"),
+            ));
         }
+        let at = Some((parent, name.to_os_string(), identifier));
+        Ok((
+            d,
+            Self {
+                at,
+                _io: PhantomData,
+            },
+        ))
+    }
+
+    /// Remove the root, now emptied, at `path`. On Unix it is first checked to
+    /// still be the directory opened, failing with
+    /// [`RemoveErrorKind::Replaced`] if not.
+    pub(crate) fn remove(self, path: &Path) -> Result<(), RemoveError> {
+        let fail = |operation, e| root_error::<I>(operation, path, e);
+        #[cfg(not(windows))]
+        if let Some((parent, name, identifier)) = self.at {
+            let found =
+                I::unique_identifier_at(&parent, &name).map_err(|e| fail(Operation::Stat, e))?;
+            if found != identifier {
+                return Err(RemoveError::new(
+                    path.into(),
+                    Operation::Rmdir,
+                    RemoveErrorKind::Replaced,
+                    std_io::Error::other("replaced since its contents were removed"),
+                ));
+            }
+            // A directory swapped in after the check is only removed if it is
+            // empty
+            return fs_at::OpenOptions::default()
+                .rmdir_at(&parent, &name)
+                .map_err(|e| fail(Operation::Rmdir, e));
+        }
+        std::fs::remove_dir(path).map_err(|e| fail(Operation::Rmdir, e))
+    }
+}
+
+#[cfg(not(windows))]
+use crate::EntryId;
+use crate::{
+    Filesystem, FilesystemPolicy, Operation, ParallelMode, RemovalReport, RemoveError,
+    RemoveErrorKind, Remover, RemoverBuilder,
+};
+
+use self::{
+    dir_stack::{DirStack, Levels},
+    io::{BatchError, DirEntry, EntryKind, EntryMetadata},
+    outcome::{Outcome, Phase},
+    path_components::{Child, LazyPath, PathComponents},
+};
+
+/// Names read from a directory before its stream is closed again.
+pub(crate) const SCAN_BATCH: usize = 1024;
+
+/// Names read at once when entries may be sorted by inode: sorting small
+/// batches gains little. This is the batch size gnulib's fts uses.
+const SORTED_SCAN_BATCH: usize = 100_000;
+
+// Core workhorse: dispatch to the IO backend and engine the remover asks for.
+// Returns whether `d` was emptied, which in keep-going mode it may not be.
+fn remove_dir_contents_with<I: io::Io>(
+    d: File,
+    debug_root: &Path,
+    remover: &Remover,
+    outcome: &Outcome,
+) -> Result<bool, RemoveError> {
+    let remover = &remover.for_filesystem::<I>(&d);
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if remover.io_uring {
+        return remove_dir_contents_engine::<uring::UringIo<I>>(d, debug_root, remover, outcome);
+    }
+    remove_dir_contents_engine::<I>(d, debug_root, remover, outcome)
+}
+
+fn remove_dir_contents_engine<I: io::Io>(
+    d: File,
+    debug_root: &Path,
+    remover: &Remover,
+    outcome: &Outcome,
+) -> Result<bool, RemoveError> {
+    match remover.parallel_mode() {
+        ParallelMode::Serial => remove_dir_contents_serial::<I>(d, debug_root, remover, outcome)
+            .map(|root| root.is_some()),
         #[cfg(feature = "parallel")]
-        _ => {
-            let iter = fs_at::read_dir(&mut d)?;
-            let iter = iter.par_bridge();
-            iter.try_for_each(|dir_entry| {
-                scan_and_remove_entry_recursively::<I>(debug_root, &dirfd, dir_entry, parallel)
-            })?;
+        ParallelMode::Parallel => {
+            parallel::remove_dir_contents::<I>(d, debug_root, remover, outcome)
+        }
+        #[cfg(feature = "threads")]
+        ParallelMode::Threads(_) => {
+            parallel::remove_dir_contents::<I>(d, debug_root, remover, outcome)
+        }
+    }
+}
+
+/// Serial deletion without recursion: the directories between `root` and the
+/// one being emptied are tracked by a [`DirStack`], which bounds the number of
+/// descriptors held no matter how deep the tree is. Returns the root handle,
+/// or `None` if entries were left in it in keep-going mode.
+fn remove_dir_contents_serial<I: io::Io>(
+    root: File,
+    debug_root: &Path,
+    remover: &Remover,
+    outcome: &Outcome,
+) -> Result<Option<File>, RemoveError> {
+    let mut levels = Levels::new(root);
+    let mut stack = DirStack::new(&mut levels, debug_root, remover, outcome);
+    while serial_step::<I>(&mut stack, outcome, |_, _| ())? {}
+    Ok(levels.into_root())
+}
+
+/// Take one step of serial deletion: remove or descend into the next entry of
+/// the top directory, or remove the top directory once emptied. `removed` is
+/// told of each entry removed. Returns whether there is more to do.
+pub(crate) fn serial_step<I: io::Io>(
+    stack: &mut DirStack<'_>,
+    outcome: &Outcome,
+    mut removed: impl FnMut(&dyn LazyPath, Option<EntryMetadata>),
+) -> Result<bool, RemoveError> {
+    let next = stack.next_pending::<I>().or_else(|e| {
+        // Leave the top directory, and whatever is still in it
+        outcome.absorb(e)?;
+        stack.abandon_top();
+        Ok(None)
+    })?;
+    let Some(entry) = next else {
+        if stack.is_root() {
+            return Ok(false);
+        }
+        // The top directory is now empty, unless entries were left in it
+        let frame = stack.pop();
+        let name = frame.name().to_os_string();
+        let emptied = !frame.is_incomplete();
+        let handle = frame.into_handle();
+        if let Err(e) = stack.ensure_top_open::<I>() {
+            outcome.absorb(e)?;
+            stack.abandon_top();
+            return Ok(true);
         }
+        let kept = {
+            let path = stack.path_of(&name);
+            if emptied {
+                match remove_empty_dir::<I>(stack.top_handle(), &name, handle, &path, outcome) {
+                    Ok(metadata) => {
+                        removed(&path, metadata);
+                        false
+                    }
+                    Err(e) => {
+                        outcome.absorb(e)?;
+                        true
+                    }
+                }
+            } else {
+                outcome.leave(path.to_path_buf());
+                true
+            }
+        };
+        if kept {
+            stack.keep(name);
+        }
+        return Ok(true);
     };
+    let child = stack.retry_starved::<I, _, _>(|stack| {
+        stack.ensure_top_open::<I>()?;
+        let path = stack.path_of(&entry.name);
+        let removal =
+            remove_entry::<I>(stack.top_handle(), &entry.name, entry.kind, &path, outcome);
+        if let Ok(Removal::Removed(metadata)) = removal {
+            removed(&path, metadata);
+        }
+        removal
+    });
+    match child {
+        Ok(Removal::Dir(child)) => stack.push(entry.name, child),
+        Ok(Removal::Removed(_)) => (),
+        Ok(Removal::Skipped) => stack.keep(entry.name),
+        Err(e) => {
+            outcome.absorb(e)?;
+            stack.keep(entry.name);
+        }
+    }
+    Ok(true)
+}
 
-    #[cfg(feature = "log")]
-    log::trace!("scanned {}", &debug_root);
-    Ok(dirfd)
-}
-
-#[allow(clippy::map_identity)]
-fn scan_and_remove_entry_recursively<I: io::Io>(
-    debug_root: &PathComponents<'_>,
-    dirfd: &File,
-    dir_entry: Result<fs_at::DirEntry>,
-    parallel: ParallelMode,
-) -> Result<()> {
-    let dir_entry = dir_entry?;
-    let name = dir_entry.name();
-    if name == OsStr::new(".") || name == OsStr::new("..") {
-        return Ok(());
-    }
-    let dir_path = Path::new(name);
-    let dir_debug_root = PathComponents::Component(debug_root, dir_path);
-    #[cfg(windows)]
+/// Read the next batch of entries from the directory `d` at `path`, in the
+/// order `remover` asks for, and have the backend remove what it can in bulk.
+/// Returns the entries left to remove one at a time, and whether the end of
+/// the directory was reached. The names in `kept` were left in place in
+/// keep-going mode, and are skipped.
+fn scan_batch<I: io::Io>(
+    d: &File,
+    path: &dyn LazyPath,
+    remover: &Remover,
+    kept: &HashSet<OsString>,
+    outcome: &Outcome,
+) -> Result<(Vec<DirEntry>, bool), RemoveError> {
+    let limit = match remover.inode_order {
+        Some(threshold) => threshold.max(SORTED_SCAN_BATCH),
+        None => remover.scan_batch.unwrap_or(SCAN_BATCH),
+    };
+    // Each scan reads from the start, so entries left in place come round
+    // again; read enough to see a full batch past them.
+    let limit = limit + kept.len();
+    outcome.checkpoint(path, Operation::ReadDir)?;
+    outcome.scanning(path);
+    let timer = outcome.timer(Phase::Scan);
+    let mut entries = I::read_dir(d, limit).map_err(|e| error::<I>(Operation::ReadDir, path, e))?;
+    drop(timer);
+    let exhausted = entries.len() < limit;
+    if !kept.is_empty() {
+        entries.retain(|entry| !kept.contains(&entry.name));
+    }
+    #[cfg(not(windows))]
+    if remover
+        .inode_order
+        .is_some_and(|threshold| entries.len() >= threshold)
     {
+        entries.sort_unstable_by_key(|entry| entry.ino);
+    }
+    let mut usage = Vec::new();
+    let timer = outcome.timer(Phase::Unlink);
+    let result = if outcome.batches() {
+        I::remove_batch(d, entries, outcome.wants_metadata().then_some(&mut usage))
+    } else {
+        Ok(entries)
+    };
+    drop(timer);
+    usage.into_iter().for_each(|usage| outcome.removed(usage));
+    let entries = match result {
+        Ok(entries) => entries,
+        // Hand the entry back to be removed, and fail, one at a time; the rest
+        // of the batch is read again by the next scan
+        Err(BatchError {
+            name: Some(name), ..
+        }) if remover.keep_going => {
+            let entry = DirEntry {
+                name,
+                kind: EntryKind::Unknown,
+                #[cfg(not(windows))]
+                ino: 0,
+            };
+            return Ok((vec![entry], false));
+        }
+        Err(BatchError {
+            name,
+            operation,
+            error: e,
+        }) => {
+            return Err(match name {
+                Some(name) => entry_error::<I>(operation, d, &name, &Child::new(path, &name), e),
+                None => error::<I>(operation, path, e),
+            })
+        }
+    };
+    Ok((entries, exhausted))
+}
+
+/// What [`remove_entry`] did with an entry.
+pub(crate) enum Removal {
+    /// The entry is removed; its metadata is given if it was read.
+    Removed(Option<EntryMetadata>),
+    /// The entry is a directory, opened for its contents to be removed first.
+    Dir(File),
+    /// The before-remove hook, or one-file-system mode, left the entry in
+    /// place.
+    Skipped,
+}
+
+/// Remove `name` from `parent`, unless it is a directory: then it is opened
+/// and returned, for its contents to be removed first. `kind` is the type
+/// reported when `parent` was scanned.
+#[cfg_attr(any(windows, not(feature = "log")), allow(unused_variables))]
+fn remove_entry<I: io::Io>(
+    parent: &File,
+    name: &OsStr,
+    kind: EntryKind,
+    path: &dyn LazyPath,
+    outcome: &Outcome,
+) -> Result<Removal, RemoveError> {
+    let operation = match kind {
+        EntryKind::NotDir if cfg!(not(windows)) => Operation::Unlink,
+        _ => Operation::Open,
+    };
+    outcome.checkpoint(path, operation)?;
+    let _timer = outcome.timer(Phase::Unlink);
+    let fail = |operation, e| entry_error::<I>(operation, parent, name, path, e);
+    #[cfg(windows)]
+    let usage = {
         // On windows: open the file and then decide what to do with it.
-        let mut opts = fs_at::OpenOptions::default();
-        // Could possibly drop a syscall by dropping FILE_READ_ATTRIBUTES
-        // and trusting read_dir metadata more. OTOH that would introduce a
-        // race :/.
-        opts.desired_access(DELETE | FILE_LIST_DIRECTORY | FILE_READ_ATTRIBUTES);
-        let mut child_file = opts.open_path_at(dirfd, name)?;
-        let metadata = child_file.metadata()?;
-        let is_dir = metadata.is_dir();
-        let is_symlink = metadata.is_symlink();
-        if is_dir && !is_symlink {
-            remove_dir_contents_recursive::<I>(
-                I::duplicate_fd(&mut child_file)?,
-                &dir_debug_root,
-                parallel,
-            )?;
+        let child_file = I::open_dir_at(parent, name).map_err(|e| fail(Operation::Open, e))?;
+        let metadata = child_file
+            .metadata()
+            .map_err(|e| fail(Operation::Stat, e))?;
+        let usage = read_metadata(outcome, || I::metadata(&child_file))
+            .map_err(|e| fail(Operation::Stat, e))?;
+        if !outcome.before_remove(path, usage.as_ref())? {
+            return Ok(Removal::Skipped);
+        }
+        if metadata.is_dir() && !metadata.is_symlink() {
+            return Ok(Removal::Dir(child_file));
         }
         #[cfg(feature = "log")]
-        log::trace!("delete: {}", &dir_debug_root);
+        log::trace!("delete: {}", path);
         child_file.delete_by_handle().map_err(|(_f, e)| {
             #[cfg(feature = "log")]
-            log::debug!("error removing {}", dir_debug_root);
-            e
+            log::debug!("error removing {}", path);
+            fail(Operation::DeleteByHandle, e)
         })?;
-    }
+        usage
+    };
     #[cfg(not(windows))]
-    {
+    let usage = {
+        let usage_at = || {
+            read_metadata(outcome, || I::metadata_at(parent, name))
+                .map_err(|e| fail(Operation::Stat, e))
+        };
+        // Trust but verify the scanned type: a non-directory is unlinked
+        // straight away, saving an open and fstat. If it has since been
+        // replaced by a directory the unlink fails, and it is handled as an
+        // entry of unknown type below.
+        let usage = match kind {
+            EntryKind::NotDir => usage_at()?,
+            _ => None,
+        };
+        // One read as a directory is handled below, as a failed unlink would be
+        let replaced = usage.is_some_and(|usage| usage.file_type == io::EntryType::Dir);
+        if kind == EntryKind::NotDir && !replaced {
+            if !outcome.before_remove(path, usage.as_ref())? {
+                return Ok(Removal::Skipped);
+            }
+            #[cfg(feature = "log")]
+            log::trace!("unlink: {}", path);
+            match fs_at::OpenOptions::default().unlink_at(parent, name) {
+                Ok(()) => {
+                    #[cfg(feature = "log")]
+                    log::trace!("removed {}", path);
+                    outcome.removed_entry(path, usage);
+                    return Ok(Removal::Removed(usage));
+                }
+                Err(e) if I::is_dir_unlink_error(&e) => (),
+                Err(e) => {
+                    #[cfg(feature = "log")]
+                    log::debug!("error removing {}", path);
+                    return Err(fail(Operation::Unlink, e));
+                }
+            }
+        }
         // Otherwise, open the path safely but normally, fstat to see if its
         // a dir, then either unlink or recursively delete
-        let mut opts = fs_at::OpenOptions::default();
-        opts.read(true)
-            .write(fs_at::OpenOptionsWriteMode::Write)
-            .follow(false);
-        let child_result = opts.open_dir_at(dirfd, name);
-        let is_dir = match child_result {
+        match I::open_dir_at(parent, name) {
             // Errors indicating a non-directory entry (symlink, FIFO, socket,
             // regular file with O_DIRECTORY, etc.) — fall through to unlink_at.
-            Err(e) if !I::is_not_dir_open_error(&e) => return Err(e),
-            Err(_) => false,
+            Err(e) if !I::is_not_dir_open_error(&e) => return Err(fail(Operation::Open, e)),
+            Err(_) => (),
             Ok(child_file) => {
-                let metadata = child_file.metadata()?;
-                let is_dir = metadata.is_dir();
-                if is_dir {
-                    remove_dir_contents_recursive::<I>(child_file, &dir_debug_root, parallel)?;
-                    #[cfg(feature = "log")]
-                    log::trace!("rmdir: {}", &dir_debug_root);
-                    let opts = fs_at::OpenOptions::default();
-                    opts.rmdir_at(dirfd, name).map_err(|e| {
-                        #[cfg(feature = "log")]
-                        log::debug!("error removing {}", dir_debug_root);
-                        e
-                    })?;
+                let metadata = child_file
+                    .metadata()
+                    .map_err(|e| fail(Operation::Stat, e))?;
+                if metadata.is_dir() {
+                    if !outcome.within_file_system(path, &metadata)? {
+                        return Ok(Removal::Skipped);
+                    }
+                    let usage = read_metadata(outcome, || I::metadata(&child_file))
+                        .map_err(|e| fail(Operation::Stat, e))?;
+                    if !outcome.before_remove(path, usage.as_ref())? {
+                        return Ok(Removal::Skipped);
+                    }
+                    return Ok(Removal::Dir(child_file));
                 }
-                is_dir
             }
-        };
-        if !is_dir {
-            #[cfg(feature = "log")]
-            log::trace!("unlink: {}", &dir_debug_root);
-            opts.unlink_at(dirfd, name).map_err(|e| {
+        }
+        let usage = usage_at()?;
+        if !outcome.before_remove(path, usage.as_ref())? {
+            return Ok(Removal::Skipped);
+        }
+        #[cfg(feature = "log")]
+        log::trace!("unlink: {}", path);
+        fs_at::OpenOptions::default()
+            .unlink_at(parent, name)
+            .map_err(|e| {
                 #[cfg(feature = "log")]
-                log::debug!("error removing {}", dir_debug_root);
-                e
+                log::debug!("error removing {}", path);
+                fail(Operation::Unlink, e)
             })?;
+        usage
+    };
+    #[cfg(feature = "log")]
+    log::trace!("removed {}", path);
+    outcome.removed_entry(path, usage);
+    Ok(Removal::Removed(usage))
+}
+
+/// The metadata `read` returns, if `outcome` wants it: for statistics, or
+/// for hooks.
+fn read_metadata(
+    outcome: &Outcome,
+    read: impl FnOnce() -> std_io::Result<EntryMetadata>,
+) -> std_io::Result<Option<EntryMetadata>> {
+    outcome.wants_metadata().then(read).transpose()
+}
+
+/// Remove the emptied directory `name` from `parent`. `handle` is the open
+/// directory, if still available. Returns its metadata, if it was read.
+#[cfg_attr(not(feature = "log"), allow(unused_variables))]
+fn remove_empty_dir<I: io::Io>(
+    parent: &File,
+    name: &OsStr,
+    handle: Option<File>,
+    path: &dyn LazyPath,
+    outcome: &Outcome,
+) -> Result<Option<EntryMetadata>, RemoveError> {
+    let operation = if cfg!(windows) {
+        Operation::DeleteByHandle
+    } else {
+        Operation::Rmdir
+    };
+    outcome.checkpoint(path, operation)?;
+    let _timer = outcome.timer(Phase::Rmdir);
+    let fail = |operation, e| entry_error::<I>(operation, parent, name, path, e);
+    #[cfg(feature = "log")]
+    log::trace!("rmdir: {}", path);
+    cfg_if::cfg_if! {
+        if #[cfg(windows)] {
+            // Directories are deleted through a handle opened with DELETE access
+            let handle = match handle {
+                Some(handle) => handle,
+                None => I::open_dir_at(parent, name).map_err(|e| fail(Operation::Open, e))?,
+            };
+            let usage = read_metadata(outcome, || I::metadata(&handle))
+                .map_err(|e| fail(Operation::Stat, e))?;
+            let operation = Operation::DeleteByHandle;
+            let result = handle.delete_by_handle().map_err(|(_f, e)| e);
+        } else {
+            // Close before removing: nothing more is needed from the handle
+            drop(handle);
+            let usage = read_metadata(outcome, || I::metadata_at(parent, name))
+                .map_err(|e| fail(Operation::Stat, e))?;
+            let operation = Operation::Rmdir;
+            let result = fs_at::OpenOptions::default().rmdir_at(parent, name);
         }
     }
-    #[cfg(feature = "log")]
-    log::trace!("removed {}", dir_debug_root);
+    result.map_err(|e| {
+        #[cfg(feature = "log")]
+        log::debug!("error removing {}", path);
+        fail(operation, e)
+    })?;
+    outcome.removed_entry(path, usage);
+    Ok(usage)
+}
 
-    Ok(())
+/// `e`, from `operation` on the root of a removal at `path`.
+pub(crate) fn root_error<I: io::Io>(
+    operation: Operation,
+    path: &Path,
+    e: std_io::Error,
+) -> RemoveError {
+    let kind = match I::error_kind(&e) {
+        // Nothing has raced with the removal yet: the root just isn't there
+        RemoveErrorKind::Raced if matches!(operation, Operation::Open | Operation::CreateDir) => {
+            RemoveErrorKind::NotFound
+        }
+        kind => kind,
+    };
+    RemoveError::new(path.into(), operation, kind, e)
+}
+
+/// `e`, from `operation` on the directory at `path`.
+fn error<I: io::Io>(operation: Operation, path: &dyn LazyPath, e: std_io::Error) -> RemoveError {
+    let kind = I::error_kind(&e);
+    RemoveError::new(path.to_path_buf().into(), operation, kind, e)
+}
+
+/// `e`, from `operation` on the entry `name` of `parent`, at `path`.
+#[cfg_attr(windows, allow(unused_variables))]
+fn entry_error<I: io::Io>(
+    operation: Operation,
+    parent: &File,
+    name: &OsStr,
+    path: &dyn LazyPath,
+    e: std_io::Error,
+) -> RemoveError {
+    #[allow(unused_mut)]
+    let mut kind = I::error_kind(&e);
+    // Permission to remove the entry may be denied by a flag rather than by
+    // its mode; checking costs syscalls, but only once things have failed.
+    #[cfg(not(windows))]
+    if kind == RemoveErrorKind::PermissionDenied
+        && matches!(operation, Operation::Unlink | Operation::Rmdir)
+        && I::is_immutable(parent, name)
+    {
+        kind = RemoveErrorKind::Immutable;
+    }
+    RemoveError::new(path.to_path_buf().into(), operation, kind, e)
+}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    //! Tests of the engines through an [`io::Io`] that counts and perturbs
+    //! the calls made.
+
+    use std::{
+        cell::Cell, collections::HashSet, ffi::OsStr, fs, os::unix::fs::symlink, path::Path,
+    };
+
+    use tempfile::TempDir;
+    use test_log::test;
+
+    use super::{
+        io::{DirEntry, DirPosition, EntryKind, EntryMetadata, Io},
+        outcome::Outcome,
+        path_components::PathComponents,
+        remove_dir_all_path, remove_dir_contents_serial, scan_batch, Filesystem, FilesystemPolicy,
+        Operation, OsIo, ParallelMode, RemoveErrorKind, RemoverBuilder,
+    };
+
+    thread_local! {
+        static OPENS: Cell<usize> = const { Cell::new(0) };
+        /// The most names asked of a single directory scan.
+        static LARGEST_SCAN: Cell<usize> = const { Cell::new(0) };
+        /// Reported in place of the scanned type of every entry.
+        static KIND_OVERRIDE: Cell<Option<EntryKind>> = const { Cell::new(None) };
+    }
+
+    /// Refused by [`CountingIo::open_dir_at`], as are names starting with it.
+    const UNREMOVABLE: &str = "unremovable";
+
+    /// Counts opens of directory entries, and the size of scans, and refuses
+    /// to open [`UNREMOVABLE`] ones.
+    struct CountingIo;
+
+    impl Io for CountingIo {
+        type UniqueIdentifier = <OsIo as Io>::UniqueIdentifier;
+
+        fn duplicate_fd(f: &fs::File) -> std::io::Result<fs::File> {
+            OsIo::duplicate_fd(f)
+        }
+
+        fn open_dir(p: &Path) -> std::io::Result<fs::File> {
+            OsIo::open_dir(p)
+        }
+
+        fn open_dir_at(parent: &fs::File, name: &OsStr) -> std::io::Result<fs::File> {
+            OPENS.with(|opens| opens.set(opens.get() + 1));
+            if name.to_str().is_some_and(|n| n.starts_with(UNREMOVABLE)) {
+                return Err(std::io::Error::from_raw_os_error(libc::EACCES));
+            }
+            OsIo::open_dir_at(parent, name)
+        }
+
+        fn read_dir(d: &fs::File, limit: usize) -> std::io::Result<Vec<DirEntry>> {
+            LARGEST_SCAN.with(|largest| largest.set(largest.get().max(limit)));
+            let mut entries = OsIo::read_dir(d, limit)?;
+            if let Some(kind) = KIND_OVERRIDE.with(Cell::get) {
+                entries.iter_mut().for_each(|entry| entry.kind = kind);
+            }
+            Ok(entries)
+        }
+
+        fn read_dir_from(
+            d: &fs::File,
+            position: &mut DirPosition,
+            limit: usize,
+        ) -> std::io::Result<Vec<DirEntry>> {
+            let mut entries = OsIo::read_dir_from(d, position, limit)?;
+            if let Some(kind) = KIND_OVERRIDE.with(Cell::get) {
+                entries.iter_mut().for_each(|entry| entry.kind = kind);
+            }
+            Ok(entries)
+        }
+
+        fn metadata(f: &fs::File) -> std::io::Result<EntryMetadata> {
+            OsIo::metadata(f)
+        }
+
+        fn metadata_at(parent: &fs::File, name: &OsStr) -> std::io::Result<EntryMetadata> {
+            OsIo::metadata_at(parent, name)
+        }
+
+        fn filesystem(d: &fs::File) -> std::io::Result<Filesystem> {
+            OsIo::filesystem(d)
+        }
+
+        fn unique_identifier(d: &fs::File) -> std::io::Result<Self::UniqueIdentifier> {
+            OsIo::unique_identifier(d)
+        }
+
+        fn unique_identifier_at(
+            parent: &fs::File,
+            name: &OsStr,
+        ) -> std::io::Result<Self::UniqueIdentifier> {
+            OsIo::unique_identifier_at(parent, name)
+        }
+
+        fn is_not_dir_open_error(e: &std::io::Error) -> bool {
+            OsIo::is_not_dir_open_error(e)
+        }
+
+        fn is_dir_unlink_error(e: &std::io::Error) -> bool {
+            OsIo::is_dir_unlink_error(e)
+        }
+
+        fn error_kind(e: &std::io::Error) -> RemoveErrorKind {
+            OsIo::error_kind(e)
+        }
+
+        fn is_immutable(parent: &fs::File, name: &OsStr) -> bool {
+            OsIo::is_immutable(parent, name)
+        }
+
+        fn is_handle_exhaustion_error(e: &std::io::Error) -> bool {
+            OsIo::is_handle_exhaustion_error(e)
+        }
+    }
+
+    const FILES: usize = 100;
+    const DIRS: usize = 2;
+    const FILES_PER_DIR: usize = 10;
+
+    /// Empty a tree of files, symlinks and two small directories, returning
+    /// the number of entries opened.
+    fn opens_to_empty<I: Io>(kind_override: Option<EntryKind>) -> usize {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        for i in 0..FILES {
+            fs::write(root.join(format!("f{i}")), b"x").unwrap();
+        }
+        symlink("f0", root.join("link")).unwrap();
+        for i in 0..DIRS {
+            let dir = root.join(format!("d{i}"));
+            fs::create_dir(&dir).unwrap();
+            for j in 0..FILES_PER_DIR {
+                fs::write(dir.join(format!("f{j}")), b"x").unwrap();
+            }
+        }
+
+        KIND_OVERRIDE.with(|k| k.set(kind_override));
+        OPENS.with(|opens| opens.set(0));
+        let handle = OsIo::open_dir(root).unwrap();
+        let remover = RemoverBuilder::new().serial().build();
+        remove_dir_contents_serial::<I>(
+            handle,
+            root,
+            &remover,
+            &Outcome::new(&remover, Path::new("")),
+        )
+        .unwrap();
+        assert_eq!(0, fs::read_dir(root).unwrap().count());
+        OPENS.with(Cell::get)
+    }
+
+    #[test]
+    fn scanned_types_skip_opening_non_directories() {
+        // tmpfs and the usual local filesystems all fill in d_type
+        assert_eq!(DIRS, opens_to_empty::<CountingIo>(None));
+    }
+
+    #[test]
+    fn unknown_types_open_every_entry() {
+        let entries = FILES + 1 + DIRS * (1 + FILES_PER_DIR);
+        assert_eq!(
+            entries,
+            opens_to_empty::<CountingIo>(Some(EntryKind::Unknown))
+        );
+    }
+
+    #[test]
+    fn stale_types_are_verified() {
+        // Directories reported as non-directories, as if replaced after the
+        // scan, fail to unlink and are then opened and emptied as usual.
+        assert_eq!(DIRS, opens_to_empty::<CountingIo>(Some(EntryKind::NotDir)));
+    }
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    #[test]
+    fn io_uring_opens_only_directories_one_at_a_time() {
+        use super::uring::{self, UringIo};
+
+        let entries = FILES + 1 + DIRS * (1 + FILES_PER_DIR);
+        for kind_override in [None, Some(EntryKind::Unknown), Some(EntryKind::NotDir)] {
+            let opens = opens_to_empty::<UringIo<CountingIo>>(kind_override);
+            if uring::is_available() {
+                assert_eq!(DIRS, opens, "{kind_override:?}");
+            } else if kind_override == Some(EntryKind::Unknown) {
+                assert_eq!(entries, opens);
+            }
+        }
+    }
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    #[test]
+    fn io_uring_unlinks_fifos_and_unreadable_files() {
+        use std::{ffi::CString, os::unix::fs::PermissionsExt, sync::mpsc, time::Duration};
+
+        use super::uring::UringIo;
+
+        // Opening a FIFO for reading can block until there is a writer, and a
+        // mode 000 file can't be opened for reading without privileges
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().to_path_buf();
+        let fifo = CString::new(root.join("fifo").into_os_string().into_encoded_bytes()).unwrap();
+        assert_eq!(0, unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) });
+        let unreadable = root.join("unreadable");
+        fs::write(&unreadable, b"x").unwrap();
+        fs::set_permissions(&unreadable, fs::Permissions::from_mode(0o000)).unwrap();
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("dir/f"), b"x").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            // Entries of unknown type are opened through the ring
+            KIND_OVERRIDE.with(|k| k.set(Some(EntryKind::Unknown)));
+            let handle = OsIo::open_dir(&root).unwrap();
+            let remover = RemoverBuilder::new().serial().build();
+            let outcome = Outcome::new(&remover, &root);
+            let result = remove_dir_contents_serial::<UringIo<CountingIo>>(
+                handle, &root, &remover, &outcome,
+            );
+            sender.send(result.map(drop)).unwrap();
+        });
+        receiver
+            .recv_timeout(Duration::from_secs(30))
+            .expect("removal finishes")
+            .unwrap();
+        assert_eq!(0, fs::read_dir(tmp.path()).unwrap().count());
+    }
+
+    #[test]
+    fn large_batches_are_sorted_by_inode() {
+        let tmp = TempDir::new().unwrap();
+        for i in 0..FILES {
+            fs::write(tmp.path().join(format!("f{i}")), b"x").unwrap();
+        }
+        let handle = OsIo::open_dir(tmp.path()).unwrap();
+        let path = PathComponents::new(tmp.path(), std::iter::empty());
+        let outcome = Outcome::new(&RemoverBuilder::new().build(), Path::new(""));
+
+        let remover = RemoverBuilder::new().inode_order(FILES).build();
+        let (entries, exhausted) =
+            scan_batch::<OsIo>(&handle, &path, &remover, &HashSet::new(), &outcome).unwrap();
+        assert!(exhausted);
+        assert_eq!(FILES, entries.len());
+        assert!(entries.windows(2).all(|w| w[0].ino <= w[1].ino));
+
+        // Below the threshold readdir order is kept
+        let remover = RemoverBuilder::new().inode_order(FILES + 1).build();
+        let (sorted, _) =
+            scan_batch::<OsIo>(&handle, &path, &remover, &HashSet::new(), &outcome).unwrap();
+        let remover = RemoverBuilder::new().build();
+        let (unsorted, _) =
+            scan_batch::<OsIo>(&handle, &path, &remover, &HashSet::new(), &outcome).unwrap();
+        assert!(sorted
+            .iter()
+            .map(|e| &e.name)
+            .eq(unsorted.iter().map(|e| &e.name)));
+    }
+
+    #[test]
+    fn filesystem_policy_decides_what_the_builder_leaves_open() {
+        let tmp = TempDir::new().unwrap();
+        let d = OsIo::open_dir(tmp.path()).unwrap();
+        let filesystem = OsIo::filesystem(&d).unwrap();
+        let policy = FilesystemPolicy::for_filesystem(filesystem)
+            .serial()
+            .scan_batch(7)
+            .inode_order(Some(100));
+        let builder = RemoverBuilder::new().filesystem_policy(filesystem, policy);
+
+        let remover = builder.clone().build().for_filesystem::<OsIo>(&d);
+        assert!(matches!(remover.parallel_mode(), ParallelMode::Serial));
+        assert_eq!(Some(7), remover.scan_batch);
+        assert_eq!(Some(100), remover.inode_order);
+
+        let remover = builder.clone().inode_order(5).build();
+        assert_eq!(Some(5), remover.for_filesystem::<OsIo>(&d).inode_order);
+        #[cfg(feature = "parallel")]
+        {
+            let remover = builder.parallel().build().for_filesystem::<OsIo>(&d);
+            assert!(matches!(remover.parallel_mode(), ParallelMode::Parallel));
+        }
+    }
+
+    #[test]
+    fn overridden_policies_replace_earlier_ones() {
+        let tmp = TempDir::new().unwrap();
+        let d = OsIo::open_dir(tmp.path()).unwrap();
+        let filesystem = OsIo::filesystem(&d).unwrap();
+        let policy = FilesystemPolicy::default();
+        let remover = RemoverBuilder::new()
+            .filesystem_policy(filesystem, policy.scan_batch(3))
+            .filesystem_policy(filesystem, policy.scan_batch(4))
+            .build();
+        assert_eq!(1, remover.filesystems.len());
+        assert_eq!(Some(4), remover.for_filesystem::<OsIo>(&d).scan_batch);
+    }
+
+    /// Make a tree in which the directories `a/unremovable0` to
+    /// `a/unremovable9` and `c/d/unremovable` can't be removed, with
+    /// everything else removable.
+    fn make_unremovable_tree(root: &Path) {
+        fs::create_dir(root).unwrap();
+        for i in 0..5 {
+            fs::write(root.join(format!("f{i}")), b"x").unwrap();
+        }
+        for i in 0..10 {
+            let dir = root.join("a").join(format!("{UNREMOVABLE}{i}"));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("f"), b"x").unwrap();
+        }
+        fs::write(root.join("a/f"), b"x").unwrap();
+        fs::create_dir_all(root.join("b/d")).unwrap();
+        fs::write(root.join("b/f"), b"x").unwrap();
+        fs::write(root.join("b/d/f"), b"x").unwrap();
+        fs::create_dir_all(root.join("c/d").join(UNREMOVABLE)).unwrap();
+        fs::write(root.join("c/d").join(UNREMOVABLE).join("f"), b"x").unwrap();
+    }
+
+    /// Remove an unremovable tree, in batches small enough that the entries
+    /// left in place fill whole batches.
+    fn removes_what_it_can(builder: RemoverBuilder) {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        make_unremovable_tree(&root);
+        let filesystem = OsIo::filesystem(&OsIo::open_dir(&root).unwrap()).unwrap();
+        let policy = FilesystemPolicy::for_filesystem(filesystem)
+            .scan_batch(4)
+            .inode_order(None);
+        let remover = builder
+            .filesystem_policy(filesystem, policy)
+            .keep_going()
+            .build();
+
+        let err = remove_dir_all_path::<CountingIo, _>(&root, &remover).unwrap_err();
+        assert_eq!(11, err.failures().count());
+        for failure in err.failures() {
+            assert_eq!(Operation::Open, failure.operation());
+            assert_eq!(RemoveErrorKind::PermissionDenied, failure.kind());
+            let name = failure.path().file_name().unwrap().to_str().unwrap();
+            assert!(name.starts_with(UNREMOVABLE), "{failure}");
+        }
+        assert!(err.to_string().ends_with("(and 10 more failures)"), "{err}");
+        // Deepest first, so the root last
+        let remaining = err.remaining();
+        let position = |path: &str| remaining.iter().position(|p| *p == root.join(path));
+        assert_eq!(4, remaining.len(), "{remaining:?}");
+        assert!(position("c/d") < position("c"), "{remaining:?}");
+        assert!(position("a").is_some(), "{remaining:?}");
+        assert_eq!(Some(&root), remaining.last());
+
+        let mut left: Vec<_> = fs::read_dir(&root)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(["a", "c"].map(std::ffi::OsString::from)[..], left[..]);
+        assert_eq!(10, fs::read_dir(root.join("a")).unwrap().count());
+        assert!(root.join("c/d").join(UNREMOVABLE).join("f").exists());
+    }
+
+    #[test]
+    fn keep_going_removes_what_it_can_serially() {
+        removes_what_it_can(RemoverBuilder::new().serial());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn keep_going_removes_what_it_can_in_parallel() {
+        removes_what_it_can(RemoverBuilder::new().parallel());
+    }
+
+    #[cfg(feature = "threads")]
+    #[test]
+    fn keep_going_removes_what_it_can_on_std_threads() {
+        removes_what_it_can(RemoverBuilder::new().std_threads(3));
+    }
+
+    #[test]
+    fn stops_at_the_first_failure_by_default() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        make_unremovable_tree(&root);
+        let remover = RemoverBuilder::new().serial().build();
+        let err = remove_dir_all_path::<CountingIo, _>(&root, &remover).unwrap_err();
+        assert_eq!(1, err.failures().count());
+        assert!(err.remaining().is_empty());
+    }
+
+    #[test]
+    fn walks_beneath_without_following_symlinks() {
+        use super::resolve::Resolution;
+
+        let tmp = TempDir::new().unwrap();
+        fs::create_dir_all(tmp.path().join("a/b/c")).unwrap();
+        fs::create_dir(tmp.path().join("outside")).unwrap();
+        symlink("../outside", tmp.path().join("a/link")).unwrap();
+        let base = Resolution::Beneath(OsIo::open_dir(tmp.path()).unwrap().into());
+        OPENS.with(|opens| opens.set(0));
+        let (_, name) = base
+            .open_parent::<CountingIo>(Path::new("a/./b/c"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(OsStr::new("c"), name);
+        // CountingIo can't open a path at once, so each name is opened
+        assert_eq!(2, OPENS.with(Cell::get));
+        let open = |path| base.open_dir::<CountingIo>(Path::new(path)).unwrap();
+        assert_eq!(
+            RemoveErrorKind::NotBeneath,
+            open("a/link").unwrap_err().kind()
+        );
+        assert_eq!(
+            RemoveErrorKind::NotBeneath,
+            open("a/../a").unwrap_err().kind()
+        );
+        // Strictly, symlinks are refused, and so is `..` after a name, which
+        // would go back to `outside` from `a/link`
+        let strict = Resolution::Strict;
+        let open = |path: &Path| strict.open_dir::<CountingIo>(path).unwrap();
+        let err = open(&tmp.path().join("a/link/sub")).unwrap_err();
+        assert_eq!(RemoveErrorKind::SymlinkInPath, err.kind());
+        for path in ["a/link/../b", "a/b/../b"] {
+            let err = open(&tmp.path().join(path)).unwrap_err();
+            assert_eq!(RemoveErrorKind::Other, err.kind(), "{path}");
+        }
+        let cwd = std::env::current_dir().unwrap();
+        let up = cwd.parent().map_or(Path::new("/"), Path::new);
+        let from_up = Path::new("..").join(cwd.strip_prefix(up).unwrap());
+        open(&from_up).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn immutable_files_are_not_opened() {
+        // Unlinking an immutable file fails with EPERM, which on Linux says
+        // nothing of directories. Skipped where `chattr` can't set the flag.
+        let tmp = TempDir::new().unwrap();
+        let file = tmp.path().join("immutable");
+        fs::write(&file, b"x").unwrap();
+        let chattr = |flag| {
+            std::process::Command::new("chattr")
+                .arg(flag)
+                .arg(&file)
+                .status()
+        };
+        if !chattr("+i").is_ok_and(|status| status.success()) {
+            return;
+        }
+        OPENS.with(|opens| opens.set(0));
+        let handle = OsIo::open_dir(tmp.path()).unwrap();
+        let remover = RemoverBuilder::new().serial().build();
+        let outcome = Outcome::new(&remover, tmp.path());
+        let result =
+            remove_dir_contents_serial::<CountingIo>(handle, tmp.path(), &remover, &outcome);
+        chattr("-i").unwrap();
+        let err = result.map(drop).unwrap_err();
+        assert_eq!(RemoveErrorKind::Immutable, err.kind());
+        assert_eq!(Operation::Unlink, err.operation());
+        assert_eq!(0, OPENS.with(Cell::get));
+    }
+
+    #[test]
+    fn stops_at_mount_points() {
+        use std::os::unix::fs::MetadataExt;
+
+        use super::mounts::Mounts;
+        use crate::OnMountPoint;
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("d0")).unwrap();
+        fs::create_dir_all(root.join("d1/sub/mnt")).unwrap();
+        fs::write(root.join("d1/sub/mnt/f"), b"x").unwrap();
+        let dev = fs::metadata(root).unwrap().dev();
+        let remover = RemoverBuilder::new().serial().build();
+        let remove = |mounts| {
+            let outcome = Outcome::new(&remover, root).with_mounts(mounts);
+            let handle = OsIo::open_dir(root).unwrap();
+            let result = remove_dir_contents_serial::<OsIo>(handle, root, &remover, &outcome);
+            outcome.finish(result.map(drop))
+        };
+
+        // A bind mount from the same device, found by its path
+        let points = HashSet::from([Path::new("d1/sub/mnt").to_path_buf()]);
+        let report = remove(Mounts::new(dev, points, OnMountPoint::Skip)).unwrap();
+        assert_eq!(1, report.skipped());
+        assert_eq!(&[root.join("d1/sub/mnt")], report.mount_points());
+        assert!(!root.join("d0").exists());
+        assert!(root.join("d1/sub/mnt/f").exists());
+
+        // Another device, found by comparing
+        let mounts = Mounts::new(dev ^ 1, HashSet::new(), OnMountPoint::Fail);
+        let err = remove(mounts).unwrap_err();
+        assert_eq!(RemoveErrorKind::MountPoint, err.kind());
+        assert_eq!(root.join("d1"), err.path());
+        assert!(root.join("d1/sub/mnt/f").exists());
+    }
+
+    #[test]
+    fn walk_lists_in_batches_without_crossing_mount_points() {
+        use std::os::unix::fs::MetadataExt;
+
+        use super::{mounts::Mounts, prescan};
+        use crate::OnMountPoint;
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("d/mnt")).unwrap();
+        fs::write(root.join("d/mnt/f"), b"x").unwrap();
+        for i in 0..5 {
+            fs::write(root.join(format!("d/f{i}")), b"x").unwrap();
+        }
+        let dev = fs::metadata(root).unwrap().dev();
+        let mut remover = RemoverBuilder::new().serial().build();
+        remover.scan_batch = Some(2);
+        let walk = |on_mount_point| {
+            let points = HashSet::from([Path::new("d/mnt").to_path_buf()]);
+            let mounts = Mounts::new(dev, points, on_mount_point);
+            let outcome = Outcome::new(&remover, root).with_mounts(mounts);
+            let handle = OsIo::open_dir(root).unwrap();
+            let mut listed = Vec::new();
+            prescan::walk::<CountingIo>(&handle, root, &remover, &outcome, |path, _| {
+                listed.push(path.strip_prefix(root).unwrap().to_path_buf())
+            })
+            .map(|()| listed)
+        };
+
+        let mut listed = walk(OnMountPoint::Skip).unwrap();
+        listed.sort();
+        let mut expected = vec![Path::new("d").to_path_buf()];
+        expected.extend((0..5).map(|i| Path::new("d").join(format!("f{i}"))));
+        assert_eq!(expected, listed);
+
+        let err = walk(OnMountPoint::Fail).unwrap_err();
+        assert_eq!(RemoveErrorKind::MountPoint, err.kind());
+        assert_eq!(root.join("d/mnt"), err.path());
+    }
+
+    #[test]
+    fn steps_scan_small_batches() {
+        use super::steps::{Steps, STEP_SCAN_BATCH};
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        fs::create_dir(&root).unwrap();
+        for i in 0..STEP_SCAN_BATCH * 3 {
+            fs::write(root.join(format!("f{i}")), b"x").unwrap();
+        }
+        LARGEST_SCAN.with(|largest| largest.set(0));
+        // Inode order would otherwise read the directory whole, to sort it
+        let remover = RemoverBuilder::new().inode_order(1).build();
+        let removed = Steps::<CountingIo>::new(root.clone(), &remover).count();
+        assert_eq!(STEP_SCAN_BATCH * 3 + 1, removed);
+        assert!(!root.exists());
+        assert_eq!(STEP_SCAN_BATCH, LARGEST_SCAN.with(Cell::get));
+    }
+
+    #[test]
+    fn reopening_refuses_a_swapped_directory() {
+        use super::dir_stack::{DirStack, Levels};
+
+        // Deep enough for levels near the top to be evicted from the cache
+        const DEPTH: usize = 70;
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let leaf: std::path::PathBuf = std::iter::once(root.as_os_str())
+            .chain(std::iter::repeat_n(OsStr::new("d"), DEPTH))
+            .collect();
+        fs::create_dir_all(&leaf).unwrap();
+        let remover = RemoverBuilder::new().serial().build();
+        let outcome = Outcome::new(&remover, root);
+        let mut levels = Levels::new(OsIo::open_dir(root).unwrap());
+        let mut stack = DirStack::new(&mut levels, root, &remover, &outcome);
+        for _ in 0..DEPTH {
+            let child = OsIo::open_dir_at(stack.top_handle(), OsStr::new("d")).unwrap();
+            stack.push("d".into(), child);
+        }
+        stack.pop();
+
+        // The new top was evicted when its child was pushed
+        let top = leaf.parent().unwrap();
+        fs::rename(top, root.join("moved")).unwrap();
+        fs::create_dir(top).unwrap();
+        let err = stack.ensure_top_open::<OsIo>().unwrap_err();
+        assert_eq!(RemoveErrorKind::Raced, err.kind());
+        assert_eq!(top, err.path());
+    }
 }
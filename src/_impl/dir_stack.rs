@@ -0,0 +1,419 @@
+//! The chain of directories from the root of a removal down to the directory
+//! currently being emptied.
+//!
+//! Holding one handle per level makes the descriptor cost of a removal
+//! proportional to the depth of the tree. Instead every level is kept as a
+//! [`Frame`] that remembers its name, and handles are only cached while that
+//! is cheap: the first [`ALWAYS_CACHED_DEPTH`] levels, then up to
+//! [`MAX_CACHED_HANDLES`] handles, then a lg-spaced selection
+//! (level10/skipped1/level12/skipped2/skipped3/skipped4/level16...). A level
+//! whose handle was evicted is reopened by re-traversing from the closest
+//! ancestor that still has one, and must still be the directory it was, by
+//! device and inode, for removal to carry on.
+//!
+//! If the process runs out of descriptors the stack sheds every handle except
+//! the root and the directory being worked on, and stops caching; from then on
+//! deletion needs only a handful of descriptors, at the cost of re-traversal
+//! that is quadratic in the depth of the tree.
+
+#[cfg(not(windows))]
+use std::os::unix::fs::MetadataExt;
+use std::{
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fs::File,
+    path::Path,
+};
+
+use crate::{Operation, RemoveError, RemoveErrorKind, Remover};
+
+use super::{
+    entry_error, error,
+    io::{DirEntry, DirPosition, Io},
+    outcome::Outcome,
+    path_components::{LazyPath, PathComponents},
+    scan_batch, SCAN_BATCH,
+};
+
+/// Levels shallower than this always keep their handle.
+const ALWAYS_CACHED_DEPTH: usize = 10;
+
+/// Handles kept before the cache falls back to lg-spaced levels.
+const MAX_CACHED_HANDLES: usize = 64;
+
+/// One directory between the root and the directory being emptied.
+pub(crate) struct Frame {
+    /// Name relative to the parent frame; empty for the root.
+    name: OsString,
+    /// `None` when evicted from the cache.
+    handle: Option<File>,
+    /// The device and inode of the directory, taken from its handle when
+    /// evicted, to check that reopening it by name finds the same directory.
+    #[cfg(not(windows))]
+    id: Option<(u64, u64)>,
+    /// Entries read from the directory and not yet removed.
+    pending: Vec<DirEntry>,
+    /// The last scan reached the end of the directory.
+    exhausted: bool,
+    /// How far the directory has been read, when listing it rather than
+    /// removing its entries.
+    position: DirPosition,
+    /// Entries left in place in keep-going mode.
+    kept: HashSet<OsString>,
+    /// Entries were left in place, so the directory can't be removed.
+    incomplete: bool,
+}
+
+impl Frame {
+    fn new(name: OsString, handle: File) -> Self {
+        Self {
+            name,
+            handle: Some(handle),
+            #[cfg(not(windows))]
+            id: None,
+            pending: Vec::new(),
+            exhausted: false,
+            position: DirPosition::default(),
+            kept: HashSet::new(),
+            incomplete: false,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    pub(crate) fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+
+    /// The handle of the frame, if it was still cached when it was popped.
+    pub(crate) fn into_handle(self) -> Option<File> {
+        self.handle
+    }
+}
+
+/// The frames of a [`DirStack`], owned apart from it so that removal can be
+/// driven a step at a time, with a stack over them for each step.
+pub(crate) struct Levels {
+    frames: Vec<Frame>,
+    /// Number of frames holding a handle, including the root.
+    open: usize,
+    /// Descriptors ran out: cache nothing beyond the root and the top.
+    starved: bool,
+}
+
+impl Levels {
+    pub(crate) fn new(root: File) -> Self {
+        Self {
+            frames: vec![Frame::new(OsString::new(), root)],
+            open: 1,
+            starved: false,
+        }
+    }
+
+    /// The root handle, unless entries were left in the root.
+    pub(crate) fn into_root(mut self) -> Option<File> {
+        let root = self.frames.swap_remove(0);
+        let handle = root.handle.expect("the root handle is never evicted");
+        (!root.incomplete).then_some(handle)
+    }
+}
+
+pub(crate) struct DirStack<'p> {
+    debug_root: &'p Path,
+    remover: &'p Remover,
+    outcome: &'p Outcome,
+    levels: &'p mut Levels,
+}
+
+impl<'p> DirStack<'p> {
+    pub(crate) fn new(
+        levels: &'p mut Levels,
+        debug_root: &'p Path,
+        remover: &'p Remover,
+        outcome: &'p Outcome,
+    ) -> Self {
+        Self {
+            debug_root,
+            remover,
+            outcome,
+            levels,
+        }
+    }
+
+    pub(crate) fn is_root(&self) -> bool {
+        self.levels.frames.len() == 1
+    }
+
+    /// Display the path of `leaf` in the top directory.
+    pub(crate) fn path_of<'a>(
+        &'a self,
+        leaf: &'a OsStr,
+    ) -> PathComponents<'a, impl Iterator<Item = &'a OsStr> + Clone> {
+        PathComponents::new(
+            self.debug_root,
+            self.levels.frames[1..]
+                .iter()
+                .map(Frame::name)
+                .chain(Some(leaf)),
+        )
+    }
+
+    /// Display the path of the frames up to and including `depth`.
+    fn path_to(&self, depth: usize) -> PathComponents<'_, impl Iterator<Item = &OsStr> + Clone> {
+        PathComponents::new(
+            self.debug_root,
+            self.levels.frames[1..=depth].iter().map(Frame::name),
+        )
+    }
+
+    /// The next entry to remove from the top directory, scanning it as needed.
+    /// `None` means the top directory has been emptied.
+    pub(crate) fn next_pending<I: Io>(&mut self) -> Result<Option<DirEntry>, RemoveError> {
+        let top = self.levels.frames.len() - 1;
+        // A scan leaves nothing pending if the whole batch was removed in bulk
+        while self.levels.frames[top].pending.is_empty() && !self.levels.frames[top].exhausted {
+            let (entries, exhausted) = self.retry_starved::<I, _, _>(|stack| {
+                stack.ensure_top_open::<I>()?;
+                #[cfg(feature = "log")]
+                log::trace!("scanning {}", stack.path_to(top));
+                let frame = &stack.levels.frames[top];
+                scan_batch::<I>(
+                    frame.handle.as_ref().expect("top is open"),
+                    &stack.path_to(top),
+                    stack.remover,
+                    &frame.kept,
+                    stack.outcome,
+                )
+            })?;
+            let frame = &mut self.levels.frames[top];
+            frame.exhausted = exhausted;
+            frame.pending = entries;
+            // pop() from the back should yield readdir order
+            frame.pending.reverse();
+        }
+        Ok(self.levels.frames[top].pending.pop())
+    }
+
+    /// The next entry of the top directory, reading it a batch at a time
+    /// without removing anything, for walking a tree before removing it.
+    /// `None` means every entry has been read.
+    pub(crate) fn next_listed<I: Io>(&mut self) -> Result<Option<DirEntry>, RemoveError> {
+        let top = self.levels.frames.len() - 1;
+        let limit = self.remover.scan_batch.unwrap_or(SCAN_BATCH);
+        if self.levels.frames[top].pending.is_empty() && !self.levels.frames[top].exhausted {
+            let (entries, position) = self.retry_starved::<I, _, _>(|stack| {
+                stack.ensure_top_open::<I>()?;
+                #[cfg(feature = "log")]
+                log::trace!("listing {}", stack.path_to(top));
+                let frame = &stack.levels.frames[top];
+                let handle = frame.handle.as_ref().expect("top is open");
+                let mut position = frame.position;
+                let path = stack.path_to(top);
+                stack.outcome.checkpoint(&path, Operation::ReadDir)?;
+                let entries = I::read_dir_from(handle, &mut position, limit)
+                    .map_err(|e| error::<I>(Operation::ReadDir, &path, e))?;
+                Ok((entries, position))
+            })?;
+            let frame = &mut self.levels.frames[top];
+            frame.exhausted = entries.len() < limit;
+            frame.position = position;
+            frame.pending = entries;
+            frame.pending.reverse();
+        }
+        Ok(self.levels.frames[top].pending.pop())
+    }
+
+    /// Leave `name` in the top directory, and so the directory itself.
+    pub(crate) fn keep(&mut self, name: OsString) {
+        let frame = self.levels.frames.last_mut().expect("root frame");
+        frame.kept.insert(name);
+        frame.incomplete = true;
+    }
+
+    /// Give up on emptying the top directory.
+    pub(crate) fn abandon_top(&mut self) {
+        let frame = self.levels.frames.last_mut().expect("root frame");
+        frame.pending.clear();
+        frame.exhausted = true;
+        frame.incomplete = true;
+    }
+
+    /// Handle of the top directory. [`Self::ensure_top_open`] must have been
+    /// called since the stack last changed.
+    pub(crate) fn top_handle(&self) -> &File {
+        self.levels
+            .frames
+            .last()
+            .and_then(|f| f.handle.as_ref())
+            .expect("top handle was opened")
+    }
+
+    /// Reopen the top directory if its handle was evicted.
+    pub(crate) fn ensure_top_open<I: Io>(&mut self) -> Result<(), RemoveError> {
+        if self
+            .levels
+            .frames
+            .last()
+            .expect("root frame")
+            .handle
+            .is_none()
+        {
+            self.retry_starved::<I, _, _>(|stack| stack.reopen_top::<I>())?;
+        }
+        Ok(())
+    }
+
+    /// Descend into `name`, a child of the top directory opened as `handle`.
+    pub(crate) fn push(&mut self, name: OsString, handle: File) {
+        let parent_depth = self.levels.frames.len() - 1;
+        if parent_depth > 0 && !self.keep_handle(parent_depth) {
+            self.evict(parent_depth);
+        }
+        self.levels.frames.push(Frame::new(name, handle));
+        self.levels.open += 1;
+    }
+
+    /// Leave the top directory; the caller removes it from the new top.
+    pub(crate) fn pop(&mut self) -> Frame {
+        assert!(!self.is_root(), "the root frame is never popped");
+        let frame = self.levels.frames.pop().expect("root frame");
+        if frame.handle.is_some() {
+            self.levels.open -= 1;
+        }
+        frame
+    }
+
+    /// Whether the handle of the frame at `depth` stays cached once it is no
+    /// longer the top of the stack.
+    fn keep_handle(&self, depth: usize) -> bool {
+        if self.levels.starved {
+            return false;
+        }
+        depth < ALWAYS_CACHED_DEPTH
+            || self.levels.open < MAX_CACHED_HANDLES
+            || (depth - (ALWAYS_CACHED_DEPTH - 2)).is_power_of_two()
+    }
+
+    fn evict(&mut self, depth: usize) {
+        let frame = &mut self.levels.frames[depth];
+        let Some(handle) = frame.handle.take() else {
+            return;
+        };
+        #[cfg(not(windows))]
+        match handle.metadata() {
+            Ok(metadata) => frame.id = Some((metadata.dev(), metadata.ino())),
+            // Without its identity the level couldn't be checked when
+            // reopened, so it stays open
+            Err(_) => {
+                frame.handle = Some(handle);
+                return;
+            }
+        }
+        self.levels.open -= 1;
+    }
+
+    /// Reopen the top frame by re-traversing from the closest ancestor that
+    /// still has a handle. Levels at power-of-two distances above the top are
+    /// cached on the way down, while fewer than [`MAX_CACHED_HANDLES`] are
+    /// open, so that unwinding a long uncached chain costs O(n log n) opens
+    /// rather than O(n^2).
+    fn reopen_top<I: Io>(&mut self) -> Result<(), RemoveError> {
+        let target = self.levels.frames.len() - 1;
+        let base = self.levels.frames[..target]
+            .iter()
+            .rposition(|f| f.handle.is_some())
+            .expect("the root handle is never evicted");
+        #[cfg(feature = "log")]
+        log::trace!(
+            "reopening {} from {} levels up",
+            self.path_to(target),
+            target - base
+        );
+        let mut carried: Option<File> = None;
+        for level in base + 1..=target {
+            let parent = match &carried {
+                Some(f) => f,
+                None => self.levels.frames[level - 1]
+                    .handle
+                    .as_ref()
+                    .expect("parent handle was just opened"),
+            };
+            let name = &self.levels.frames[level].name;
+            let fail =
+                |operation, e| entry_error::<I>(operation, parent, name, &self.path_to(level), e);
+            let child = I::open_dir_at(parent, name).map_err(|e| fail(Operation::Open, e))?;
+            let metadata = child.metadata().map_err(|e| fail(Operation::Stat, e))?;
+            let replaced = if !metadata.is_dir() || metadata.is_symlink() {
+                Some("replaced by a non-directory during removal")
+            } else if !self.is_same_dir(level, &metadata) {
+                Some("replaced by another directory during removal")
+            } else {
+                None
+            };
+            if let Some(replaced) = replaced {
+                return Err(RemoveError::new(
+                    self.path_to(level).to_path_buf().into(),
+                    Operation::Open,
+                    RemoveErrorKind::Raced,
+                    std::io::Error::other(replaced),
+                ));
+            }
+            let keep = level == target
+                || (!self.levels.starved
+                    && self.levels.open < MAX_CACHED_HANDLES
+                    && (target - level).is_power_of_two());
+            if keep {
+                self.levels.frames[level].handle = Some(child);
+                self.levels.open += 1;
+                carried = None;
+            } else {
+                carried = Some(child);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `metadata`, of the directory reopened at `depth`, is of the
+    /// directory that was evicted there.
+    fn is_same_dir(&self, depth: usize, metadata: &std::fs::Metadata) -> bool {
+        cfg_if::cfg_if! {
+            if #[cfg(windows)] {
+                let _ = (depth, metadata);
+                true
+            } else {
+                self.levels.frames[depth].id == Some((metadata.dev(), metadata.ino()))
+            }
+        }
+    }
+
+    /// Run `op`, and if it fails because descriptors ran out, shed every
+    /// cached handle that isn't essential and try once more.
+    pub(crate) fn retry_starved<I, T, F>(&mut self, mut op: F) -> Result<T, RemoveError>
+    where
+        I: Io,
+        F: FnMut(&mut Self) -> Result<T, RemoveError>,
+    {
+        match op(self) {
+            Err(e) if I::is_handle_exhaustion_error(e.io_error()) && !self.levels.starved => {
+                self.shed();
+                op(self)
+            }
+            otherwise => otherwise,
+        }
+    }
+
+    fn shed(&mut self) {
+        #[cfg(feature = "log")]
+        log::debug!(
+            "out of file handles at {}, no longer caching",
+            self.path_to(self.levels.frames.len() - 1)
+        );
+        self.levels.starved = true;
+        let top = self.levels.frames.len() - 1;
+        for depth in 1..top {
+            self.evict(depth);
+        }
+    }
+}
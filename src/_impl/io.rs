@@ -2,23 +2,159 @@
 
 #[cfg(not(windows))]
 use std::fmt::Debug;
-use std::{fs::File, io, path::Path};
+use std::{
+    ffi::{OsStr, OsString},
+    fs::File,
+    io,
+    path::Path,
+};
+
+pub(crate) use crate::{EntryMetadata, EntryType};
+use crate::{Filesystem, Operation, RemoveErrorKind};
+
+/// The type of a directory entry as reported by the directory scan. This is
+/// only a hint: the entry may have been replaced since the scan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    Dir,
+    NotDir,
+    /// The filesystem doesn't report types, or the platform doesn't use them.
+    Unknown,
+}
+
+/// A name read from a directory.
+#[derive(Debug)]
+pub(crate) struct DirEntry {
+    pub(crate) name: OsString,
+    pub(crate) kind: EntryKind,
+    #[cfg(not(windows))]
+    pub(crate) ino: u64,
+}
+
+/// How far a directory has been read, for [`Io::read_dir_from`] to read on
+/// from there.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct DirPosition {
+    /// Entries read so far, `.` and `..` aside.
+    pub(crate) entries: usize,
+    /// The offset of the entry after them, where the OS can seek to it.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) offset: Option<libc::off_t>,
+}
+
+/// The first failure removing a batch of entries, as removing them one at a
+/// time would hit it.
+#[derive(Debug)]
+pub(crate) struct BatchError {
+    /// The entry, or `None` for the directory holding the batch.
+    pub(crate) name: Option<OsString>,
+    pub(crate) operation: Operation,
+    pub(crate) error: io::Error,
+}
 
 pub(crate) trait Io {
     #[cfg(not(windows))]
     type UniqueIdentifier: PartialEq + Debug;
 
-    fn duplicate_fd(f: &mut File) -> io::Result<File>;
+    fn duplicate_fd(f: &File) -> io::Result<File>;
 
     fn open_dir(p: &Path) -> io::Result<File>;
 
+    /// Open the entry `name` of `parent`, which may be a directory that is
+    /// about to be emptied and removed. Symlinks are not followed.
+    fn open_dir_at(parent: &File, name: &OsStr) -> io::Result<File>;
+
+    /// Read up to `limit` entries from the directory `d`, skipping `.` and
+    /// `..`.
+    ///
+    /// The directory stream is closed again before returning, so callers can
+    /// descend without holding it open; entries removed in the meantime simply
+    /// don't show up when the directory is read again.
+    fn read_dir(d: &File, limit: usize) -> io::Result<Vec<DirEntry>>;
+
+    /// Read up to `limit` entries from the directory `d` after those read
+    /// before from `position`, which is advanced past them, for walking a
+    /// tree without removing it. As with [`Io::read_dir`] the stream is
+    /// closed again before returning.
+    ///
+    /// Where the OS can't seek in a directory the entries before `position`
+    /// are read again and skipped.
+    fn read_dir_from(
+        d: &File,
+        position: &mut DirPosition,
+        limit: usize,
+    ) -> io::Result<Vec<DirEntry>> {
+        let mut entries = Self::read_dir(d, position.entries.saturating_add(limit))?;
+        entries.drain(..position.entries.min(entries.len()));
+        position.entries += entries.len();
+        Ok(entries)
+    }
+
+    /// Remove the non-directories among `entries`, just read from `parent`,
+    /// in bulk. Returns the entries still to be removed one at a time, in
+    /// their original order. Backends without bulk operations return all of
+    /// them. If `usage` is given, the usage of each entry removed is added to
+    /// it.
+    fn remove_batch(
+        parent: &File,
+        entries: Vec<DirEntry>,
+        usage: Option<&mut Vec<EntryMetadata>>,
+    ) -> Result<Vec<DirEntry>, BatchError> {
+        let _ = (parent, usage);
+        Ok(entries)
+    }
+
+    /// The usage of the open entry `f`.
+    fn metadata(f: &File) -> io::Result<EntryMetadata>;
+
+    /// The usage of the entry `name` of `parent`, without following symlinks.
+    #[cfg(not(windows))]
+    fn metadata_at(parent: &File, name: &OsStr) -> io::Result<EntryMetadata>;
+
+    /// Open the directory at `path`, relative and without `..`, beneath
+    /// `base` in a single call that refuses symlinks anywhere along it.
+    /// `None` where the OS can't, for callers to walk the path a name at a
+    /// time instead.
+    #[cfg(not(windows))]
+    fn open_dir_beneath(base: &File, path: &Path) -> Option<io::Result<File>> {
+        let _ = (base, path);
+        None
+    }
+
+    /// The kind of filesystem holding `d`.
+    fn filesystem(d: &File) -> io::Result<Filesystem>;
+
+    /// The identity of the open entry `d`, which no other entry shares while
+    /// it exists.
     #[cfg(not(windows))]
-    #[allow(dead_code)]
     fn unique_identifier(d: &File) -> io::Result<Self::UniqueIdentifier>;
 
+    /// The identity of the entry `name` of `parent`, without following
+    /// symlinks.
+    #[cfg(not(windows))]
+    fn unique_identifier_at(parent: &File, name: &OsStr) -> io::Result<Self::UniqueIdentifier>;
+
     /// Returns true if the error from `open_dir_at` indicates the entry is not
     /// a directory (e.g. symlink, FIFO, socket, regular file) and should be
     /// removed with `unlink_at` instead.
     #[cfg(not(windows))]
     fn is_not_dir_open_error(e: &io::Error) -> bool;
+
+    /// Returns true if the error from `unlink_at` indicates the entry is a
+    /// directory after all, so it must be opened and emptied instead.
+    #[cfg(not(windows))]
+    fn is_dir_unlink_error(e: &io::Error) -> bool;
+
+    /// Classify an error from any operation.
+    fn error_kind(e: &io::Error) -> RemoveErrorKind;
+
+    /// Returns true if the entry `name` of `parent`, or `parent` itself, is
+    /// flagged immutable or append-only, so that it can't be removed even
+    /// with permission.
+    #[cfg(not(windows))]
+    fn is_immutable(parent: &File, name: &OsStr) -> bool;
+
+    /// Returns true if the error indicates the process or system has run out
+    /// of file handles, so that closing some and retrying may succeed.
+    fn is_handle_exhaustion_error(e: &io::Error) -> bool;
 }
@@ -0,0 +1,145 @@
+//! Finding the mount points under the root of a removal, for
+//! [`crate::RemoverBuilder::one_file_system`].
+
+use std::{
+    collections::HashSet,
+    fs::{File, Metadata},
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use crate::OnMountPoint;
+
+/// The file system of the root of a removal, and the mount points under it.
+pub(crate) struct Mounts {
+    /// The device of the root.
+    dev: u64,
+    /// Mount points under the root, relative to it. A bind mount from the same
+    /// file system shares the root's device, so these are listed from
+    /// `/proc/self/mountinfo` where there is one.
+    points: HashSet<PathBuf>,
+    pub(crate) on_mount_point: OnMountPoint,
+}
+
+impl Mounts {
+    pub(crate) fn new(dev: u64, points: HashSet<PathBuf>, on_mount_point: OnMountPoint) -> Self {
+        Self {
+            dev,
+            points,
+            on_mount_point,
+        }
+    }
+
+    /// The mounts under the directory `d`.
+    pub(crate) fn read(d: &File, on_mount_point: OnMountPoint) -> io::Result<Self> {
+        let dev = d.metadata()?.dev();
+        let points = mount_points(d)
+            .inspect_err(|_e| {
+                #[cfg(feature = "log")]
+                log::debug!(
+                    "could not list mount points, comparing devices only: {}",
+                    _e
+                );
+            })
+            .unwrap_or_default();
+        Ok(Self::new(dev, points, on_mount_point))
+    }
+
+    /// Whether the directory at `relative_path`, of `metadata`, is a mount
+    /// point: on another device, or mounted from the same one. The path is
+    /// only asked for if there are such mounts under the root.
+    pub(crate) fn is_mount_point(
+        &self,
+        metadata: &Metadata,
+        relative_path: impl FnOnce() -> PathBuf,
+    ) -> bool {
+        metadata.dev() != self.dev
+            || (!self.points.is_empty() && self.points.contains(&relative_path()))
+    }
+}
+
+/// The mount points under `d`, relative to it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn mount_points(d: &File) -> io::Result<HashSet<PathBuf>> {
+    use std::os::fd::AsRawFd;
+
+    let root = std::fs::read_link(format!("/proc/self/fd/{}", d.as_raw_fd()))?;
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+    Ok(parse_mountinfo(&mountinfo, &root))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn mount_points(_d: &File) -> io::Result<HashSet<PathBuf>> {
+    Ok(HashSet::new())
+}
+
+/// The mount points listed in `mountinfo` under `root`, relative to it.
+#[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+fn parse_mountinfo(mountinfo: &str, root: &Path) -> HashSet<PathBuf> {
+    mountinfo
+        .lines()
+        // The fifth field is the mount point
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(unescape)
+        .filter_map(|point| {
+            let relative = point.strip_prefix(root).ok()?;
+            (!relative.as_os_str().is_empty()).then(|| relative.to_path_buf())
+        })
+        .collect()
+}
+
+/// `field` with the octal escapes the kernel writes for spaces, tabs,
+/// newlines and backslashes replaced by the bytes they stand for.
+#[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+fn unescape(field: &str) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+
+    let field = field.as_bytes();
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut i = 0;
+    while i < field.len() {
+        let octal = field
+            .get(i + 1..i + 4)
+            .filter(|_| field[i] == b'\\')
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match octal {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 4;
+            }
+            None => {
+                bytes.push(field[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::parse_mountinfo;
+
+    #[test]
+    fn lists_mount_points_under_the_root() {
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+35 22 0:30 / /tmp/root/proc rw - proc proc rw
+36 22 8:1 /srv /tmp/root/a\\040b/bind rw - ext4 /dev/sda1 rw
+37 22 0:31 / /tmp/root rw - tmpfs tmpfs rw
+38 22 0:32 / /tmp/rootless rw - tmpfs tmpfs rw
+";
+        let mut points: Vec<_> = parse_mountinfo(mountinfo, Path::new("/tmp/root"))
+            .into_iter()
+            .collect();
+        points.sort();
+        assert_eq!(
+            vec![PathBuf::from("a b/bind"), PathBuf::from("proc")],
+            points
+        );
+    }
+}
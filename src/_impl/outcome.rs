@@ -0,0 +1,490 @@
+//! What a removal did: what it removed, for the report and for progress
+//! updates, and what failed, which in keep-going mode is collected rather
+//! than ending it.
+
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(not(windows))]
+use super::mounts::Mounts;
+use super::{
+    io::{EntryMetadata, EntryType, Io},
+    path_components::LazyPath,
+    prescan::{self, Totals},
+};
+#[cfg(not(windows))]
+use crate::OnMountPoint;
+use crate::{
+    control::{self, Stop},
+    entry::Hooks,
+    progress::ProgressHandle,
+    Entry, HookAction, Operation, ProgressUpdate, RemovalControl, RemovalReport, RemoveError,
+    RemoveErrorKind, Remover,
+};
+
+/// The phases of removal that are timed.
+#[derive(Clone, Copy)]
+pub(crate) enum Phase {
+    Scan,
+    Unlink,
+    Rmdir,
+}
+
+pub(crate) struct Outcome {
+    /// The path given for the root, which hooks are given paths relative to.
+    root: PathBuf,
+    keep_going: bool,
+    errors: Mutex<Vec<RemoveError>>,
+    /// Directories left in place because of the errors.
+    remaining: Mutex<Vec<PathBuf>>,
+    started: Instant,
+    /// Collected with [`crate::RemoverBuilder::stats`], or for progress.
+    stats: Option<Stats>,
+    progress: Option<Tracker>,
+    hooks: Hooks,
+    /// Entries the before-remove hook skipped.
+    skipped: AtomicU64,
+    control: Option<RemovalControl>,
+    deadline: Option<Instant>,
+    abandoned: Option<Arc<AtomicBool>>,
+    /// Entries are removed one at a time rather than in bulk.
+    one_at_a_time: bool,
+    /// The file system removal is kept to, in one-file-system mode.
+    #[cfg(not(windows))]
+    mounts: Option<Mounts>,
+    /// Mount points skipped in one-file-system mode.
+    mount_points: Mutex<Vec<PathBuf>>,
+}
+
+/// Where progress updates go, and when the last one went.
+struct Tracker {
+    progress: ProgressHandle,
+    interval: Duration,
+    started: Instant,
+    /// Nanoseconds from `started` to the last update.
+    last: AtomicU64,
+    /// The directory last read, or empty before the first.
+    current: Mutex<PathBuf>,
+    total: Option<Totals>,
+}
+
+/// Counters updated from every thread of a parallel removal.
+#[derive(Default)]
+struct Stats {
+    files: AtomicU64,
+    dirs: AtomicU64,
+    symlinks: AtomicU64,
+    special_files: AtomicU64,
+    bytes_freed: AtomicU64,
+    /// Nanoseconds spent in each [`Phase`].
+    phases: [AtomicU64; 3],
+}
+
+/// Adds the time until it is dropped to a phase.
+pub(crate) struct Timer<'a> {
+    timing: Option<(&'a AtomicU64, Instant)>,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        if let Some((total, started)) = self.timing {
+            total.fetch_add(nanos(started.elapsed()), Ordering::Relaxed);
+        }
+    }
+}
+
+impl Outcome {
+    pub(crate) fn new(remover: &Remover, root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            keep_going: remover.keep_going,
+            errors: Mutex::default(),
+            remaining: Mutex::default(),
+            started: Instant::now(),
+            stats: (remover.stats || remover.progress.is_some()).then(Stats::default),
+            progress: remover.progress.clone().map(|progress| Tracker {
+                progress,
+                interval: remover.progress_interval,
+                started: Instant::now(),
+                last: AtomicU64::new(0),
+                current: Mutex::default(),
+                total: None,
+            }),
+            hooks: remover.hooks.clone(),
+            skipped: AtomicU64::new(0),
+            control: remover.control.clone(),
+            deadline: remover.deadline,
+            abandoned: remover.abandoned.clone(),
+            one_at_a_time: !remover.hooks.is_empty(),
+            #[cfg(not(windows))]
+            mounts: None,
+            mount_points: Mutex::default(),
+        }
+    }
+
+    /// Keep removal to the file system of `d`, if asked to.
+    pub(crate) fn one_file_system(&mut self, d: &File, remover: &Remover) -> io::Result<()> {
+        #[cfg(not(windows))]
+        if let Some(on_mount_point) = remover.one_file_system {
+            self.mounts = Some(Mounts::read(d, on_mount_point)?);
+        }
+        let _ = (d, remover);
+        Ok(())
+    }
+
+    /// Keep removal within `mounts`.
+    #[cfg(all(test, not(windows)))]
+    pub(crate) fn with_mounts(mut self, mounts: Mounts) -> Self {
+        self.mounts = Some(mounts);
+        self
+    }
+
+    /// Count the tree under `d` for progress to be measured against, if
+    /// asked to, adding the usage of `d` itself if it is to be removed too.
+    pub(crate) fn prescan<I: Io>(
+        &mut self,
+        d: &File,
+        root: Option<EntryMetadata>,
+        remover: &Remover,
+    ) {
+        let Some(tracker) = self.progress.as_mut().filter(|_| remover.prescan) else {
+            return;
+        };
+        match prescan::count::<I>(d, remover) {
+            Ok(mut totals) => {
+                root.into_iter().for_each(|usage| totals.add(usage));
+                #[cfg(feature = "log")]
+                log::debug!("pre-scan found {:?}", totals);
+                tracker.total = Some(totals);
+            }
+            Err(_e) => {
+                #[cfg(feature = "log")]
+                log::debug!("pre-scan failed, carrying on without it: {}", _e);
+            }
+        }
+        tracker.started = Instant::now();
+    }
+
+    /// In keep-going mode record `e` so the caller can carry on, leaving in
+    /// place whatever failed; otherwise return it.
+    pub(crate) fn absorb(&self, e: RemoveError) -> Result<(), RemoveError> {
+        let ends = matches!(
+            e.kind(),
+            RemoveErrorKind::Aborted | RemoveErrorKind::Cancelled | RemoveErrorKind::TimedOut
+        );
+        if !self.keep_going || ends {
+            return Err(e);
+        }
+        #[cfg(feature = "log")]
+        log::debug!("{}; carrying on", e);
+        self.errors.lock().unwrap().push(e);
+        Ok(())
+    }
+
+    /// Before `operation` on `path`, wait while removal is paused, and fail if
+    /// it has been cancelled or is past its deadline.
+    pub(crate) fn checkpoint(
+        &self,
+        path: &dyn LazyPath,
+        operation: Operation,
+    ) -> Result<(), RemoveError> {
+        let abandoned = self.abandoned.as_deref();
+        let stop = match &self.control {
+            Some(control) => control.check(self.deadline, abandoned),
+            None => control::stop(self.deadline, abandoned),
+        };
+        let (kind, e) = match stop {
+            None => return Ok(()),
+            Some(Stop::Cancelled) => (
+                RemoveErrorKind::Cancelled,
+                io::Error::other("removal cancelled"),
+            ),
+            Some(Stop::TimedOut) => (
+                RemoveErrorKind::TimedOut,
+                io::Error::new(io::ErrorKind::TimedOut, "removal deadline passed"),
+            ),
+        };
+        #[cfg(feature = "log")]
+        log::debug!("stopping before {} {}: {}", operation, path, e);
+        Err(RemoveError::new(
+            path.to_path_buf().into(),
+            operation,
+            kind,
+            e,
+        ))
+    }
+
+    /// The first failure collected in keep-going mode and not yet taken.
+    pub(crate) fn take_error(&self) -> Option<RemoveError> {
+        let mut errors = self.errors.lock().unwrap();
+        (!errors.is_empty()).then(|| errors.remove(0))
+    }
+
+    /// Record that the directory at `path` is left in place.
+    pub(crate) fn leave(&self, path: PathBuf) {
+        #[cfg(feature = "log")]
+        log::debug!("leaving {}", path.display());
+        self.remaining.lock().unwrap().push(path);
+    }
+
+    /// Whether the metadata of entries should be read before removing them.
+    pub(crate) fn wants_metadata(&self) -> bool {
+        self.stats.is_some() || !self.hooks.is_empty()
+    }
+
+    /// Have entries removed one at a time, as hooks need them to be.
+    pub(crate) fn one_at_a_time(mut self) -> Self {
+        self.one_at_a_time = true;
+        self
+    }
+
+    /// Whether entries may be removed in bulk by the IO backend.
+    pub(crate) fn batches(&self) -> bool {
+        !self.one_at_a_time
+    }
+
+    /// Ask the before-remove hook whether to remove the entry at `path`, or
+    /// descend into it. Returns whether to go ahead, or an error to abort.
+    pub(crate) fn before_remove(
+        &self,
+        path: &dyn LazyPath,
+        metadata: Option<&EntryMetadata>,
+    ) -> Result<bool, RemoveError> {
+        let (Some(hook), Some(metadata)) = (&self.hooks.before, metadata) else {
+            return Ok(true);
+        };
+        let path = path.to_path_buf();
+        let entry = Entry {
+            relative_path: self.relative(&path),
+            metadata,
+        };
+        match hook(&entry) {
+            HookAction::Remove => Ok(true),
+            HookAction::Skip => {
+                #[cfg(feature = "log")]
+                log::debug!("skipping {}", path.display());
+                self.skipped.fetch_add(1, Ordering::Relaxed);
+                Ok(false)
+            }
+            HookAction::Abort => {
+                let operation = match metadata.file_type {
+                    _ if cfg!(windows) => Operation::DeleteByHandle,
+                    EntryType::Dir => Operation::Rmdir,
+                    _ => Operation::Unlink,
+                };
+                let e = io::Error::other("aborted by the before-remove hook");
+                Err(RemoveError::new(
+                    path.into(),
+                    operation,
+                    RemoveErrorKind::Aborted,
+                    e,
+                ))
+            }
+        }
+    }
+
+    /// Count the entry at `path` removed, and tell the after-remove hook.
+    pub(crate) fn removed_entry(&self, path: &dyn LazyPath, metadata: Option<EntryMetadata>) {
+        let Some(metadata) = metadata else {
+            return;
+        };
+        self.removed(metadata);
+        if let Some(hook) = &self.hooks.after {
+            let path = path.to_path_buf();
+            hook(&Entry {
+                relative_path: self.relative(&path),
+                metadata: &metadata,
+            });
+        }
+    }
+
+    /// `path`, relative to the root.
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+
+    /// In one-file-system mode, whether to descend into the directory at
+    /// `path`, of `metadata`: a mount point is skipped and recorded, or fails
+    /// removal, as the caller chose.
+    #[cfg(not(windows))]
+    pub(crate) fn within_file_system(
+        &self,
+        path: &dyn LazyPath,
+        metadata: &std::fs::Metadata,
+    ) -> Result<bool, RemoveError> {
+        let Some(mounts) = &self.mounts else {
+            return Ok(true);
+        };
+        let mut full_path = None;
+        let is_mount_point = mounts.is_mount_point(metadata, || {
+            let path = full_path.insert(path.to_path_buf());
+            self.relative(path).to_path_buf()
+        });
+        if !is_mount_point {
+            return Ok(true);
+        }
+        let path = full_path.unwrap_or_else(|| path.to_path_buf());
+        match mounts.on_mount_point {
+            OnMountPoint::Skip => {
+                #[cfg(feature = "log")]
+                log::debug!("skipping mount point {}", path.display());
+                self.skipped.fetch_add(1, Ordering::Relaxed);
+                self.mount_points.lock().unwrap().push(path);
+                Ok(false)
+            }
+            OnMountPoint::Fail => Err(RemoveError::new(
+                path.into(),
+                Operation::Open,
+                RemoveErrorKind::MountPoint,
+                io::Error::other("a mount point, not crossed in one-file-system mode"),
+            )),
+        }
+    }
+
+    /// Count an entry removed, whose usage was read beforehand.
+    pub(crate) fn removed(&self, usage: EntryMetadata) {
+        let Some(stats) = &self.stats else {
+            return;
+        };
+        let count = match usage.file_type {
+            EntryType::File => &stats.files,
+            EntryType::Dir => &stats.dirs,
+            EntryType::Symlink => &stats.symlinks,
+            EntryType::Special => &stats.special_files,
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+        stats
+            .bytes_freed
+            .fetch_add(usage.bytes_freed(), Ordering::Relaxed);
+        if let Some(tracker) = self.due() {
+            self.update(tracker, false);
+        }
+    }
+
+    /// Note that the directory at `path` is being read.
+    pub(crate) fn scanning(&self, path: &dyn LazyPath) {
+        let Some(tracker) = &self.progress else {
+            return;
+        };
+        let due = self.due().is_some();
+        {
+            let mut current = tracker.current.lock().unwrap();
+            if due || current.as_os_str().is_empty() {
+                *current = path.to_path_buf();
+            }
+        }
+        if due {
+            self.update(tracker, false);
+        }
+    }
+
+    /// The tracker, if an update is due; it is then counted as made.
+    fn due(&self) -> Option<&Tracker> {
+        let tracker = self.progress.as_ref()?;
+        let now = nanos(tracker.started.elapsed());
+        let last = tracker.last.load(Ordering::Relaxed);
+        let due = now.saturating_sub(last) >= nanos(tracker.interval)
+            && tracker
+                .last
+                .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok();
+        due.then_some(tracker)
+    }
+
+    fn update(&self, tracker: &Tracker, finished: bool) {
+        let Some(stats) = &self.stats else {
+            return;
+        };
+        let entries_removed = [
+            &stats.files,
+            &stats.dirs,
+            &stats.symlinks,
+            &stats.special_files,
+        ]
+        .iter()
+        .map(|count| count.load(Ordering::Relaxed))
+        .sum();
+        let current = tracker.current.lock().unwrap().clone();
+        tracker.progress.0.update(&ProgressUpdate {
+            entries_removed,
+            bytes_freed: stats.bytes_freed.load(Ordering::Relaxed),
+            current_dir: &current,
+            elapsed: tracker.started.elapsed(),
+            total: tracker.total.map(|totals| (totals.entries, totals.bytes)),
+            finished,
+        });
+    }
+
+    /// Time `phase` until the timer returned is dropped.
+    pub(crate) fn timer(&self, phase: Phase) -> Timer<'_> {
+        Timer {
+            timing: self
+                .stats
+                .as_ref()
+                .map(|stats| (&stats.phases[phase as usize], Instant::now())),
+        }
+    }
+
+    /// The report, given the `result` of removal. Otherwise the error that
+    /// ended removal, or else the first error collected, carrying the rest if
+    /// there were any, and the report.
+    pub(crate) fn finish(
+        self,
+        result: Result<(), RemoveError>,
+    ) -> Result<RemovalReport, RemoveError> {
+        if let Some(tracker) = &self.progress {
+            self.update(tracker, true);
+        }
+        let report = self.report();
+        let errors = self.errors.into_inner().unwrap();
+        let mut errors = result.err().into_iter().chain(errors);
+        let Some(first) = errors.next() else {
+            return Ok(report);
+        };
+        let first = if self.keep_going {
+            let remaining = self.remaining.into_inner().unwrap();
+            first.with_rest(errors.collect(), remaining)
+        } else {
+            first
+        };
+        Err(first.with_report(report))
+    }
+
+    fn report(&self) -> RemovalReport {
+        let mut report = RemovalReport {
+            elapsed: self.started.elapsed(),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            mount_points: {
+                let mut mount_points = self.mount_points.lock().unwrap().clone();
+                mount_points.sort_unstable();
+                mount_points
+            },
+            ..RemovalReport::default()
+        };
+        if let Some(stats) = &self.stats {
+            let load = |count: &AtomicU64| count.load(Ordering::Relaxed);
+            let phase = |phase: Phase| Duration::from_nanos(load(&stats.phases[phase as usize]));
+            report.files = load(&stats.files);
+            report.dirs = load(&stats.dirs);
+            report.symlinks = load(&stats.symlinks);
+            report.special_files = load(&stats.special_files);
+            report.bytes_freed = load(&stats.bytes_freed);
+            report.scan_time = phase(Phase::Scan);
+            report.unlink_time = phase(Phase::Unlink);
+            report.rmdir_time = phase(Phase::Rmdir);
+        }
+        report
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
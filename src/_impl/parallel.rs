@@ -0,0 +1,600 @@
+//! Parallel deletion, on a rayon thread pool or on scoped std threads.
+//!
+//! The work is split into small jobs: reading a batch of names from a
+//! directory, removing one entry, and removing a directory once it has been
+//! emptied. Each directory being worked on is a heap allocated [`Dir`] that
+//! points at its parent; nothing recurses, so the depth of the tree costs heap
+//! memory rather than stack.
+//!
+//! Jobs are run by one worker per thread, up to the remover's cap on
+//! operations in flight. Each worker always picks the most important job next:
+//! 1) scanning, while few entries are queued for removal, so that the other
+//!    queues stay full and deletion does not end up accidentally serial.
+//! 2) closing and removing emptied directories, to free up descriptors.
+//! 3) removing entries, to free up directories so they can be removed.
+//! 4) scanning, when plenty of entries are already queued.
+//!
+//! Within each class deeper and then lexicographically earlier work goes
+//! first. That keeps the set of directories being worked on small, so very
+//! branchy trees don't exhaust kernel resources or application memory.
+//!
+//! Every [`Dir`] holds a handle, so only [`MAX_DIRS_IN_FLIGHT`] are kept at
+//! once, and none deeper than [`MAX_PARALLEL_DEPTH`]. Past either limit a
+//! directory is emptied by the serial engine, which bounds the descriptors
+//! needed for that subtree, from within the job that found it.
+//!
+//! With [`Remover`]'s adaptive mode the number of jobs run at once is limited
+//! further, and tuned as deletion progresses; see [`adaptive`].
+
+mod adaptive;
+
+use std::{
+    cmp::Ordering as CmpOrdering,
+    collections::{BinaryHeap, HashSet},
+    ffi::{OsStr, OsString},
+    fs::File,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::Instant,
+};
+
+#[cfg(feature = "parallel")]
+use crate::ThreadPool;
+use crate::{ParallelMode, RemoveError, Remover};
+
+use super::{
+    io::{DirEntry, Io},
+    outcome::Outcome,
+    path_components::{LazyPath, PathComponents},
+    remove_dir_contents_serial, remove_empty_dir, remove_entry, scan_batch, Removal, SCAN_BATCH,
+};
+
+use self::adaptive::Tuner;
+
+/// Directories below this depth are emptied by the serial engine.
+const MAX_PARALLEL_DEPTH: usize = 32;
+
+/// Directories worked on in parallel at once.
+const MAX_DIRS_IN_FLIGHT: usize = 64;
+
+/// Scanning is the most important work while fewer removals than this are
+/// queued.
+const LOW_WATER: usize = SCAN_BATCH;
+
+/// A directory whose contents are being removed.
+struct Dir {
+    parent: Option<Arc<Dir>>,
+    /// Name relative to the parent; empty for the root.
+    name: OsString,
+    depth: usize,
+    /// Index of each directory from the root down to this one among the
+    /// entries of its parent, giving a cheap lexicographic order.
+    position: Box<[u32]>,
+    handle: File,
+    /// Entries of the current batch not yet removed, plus one while the batch
+    /// is being scanned.
+    outstanding: AtomicUsize,
+    /// The last scan reached the end of the directory.
+    exhausted: AtomicBool,
+    /// Index for the next entry read from the directory.
+    next_index: AtomicU32,
+    /// Entries left in place in keep-going mode.
+    kept: Mutex<HashSet<OsString>>,
+    /// Entries were left in place, so the directory can't be removed.
+    incomplete: AtomicBool,
+}
+
+impl Dir {
+    fn new(parent: Option<Arc<Dir>>, index: u32, name: OsString, handle: File) -> Self {
+        let (depth, position) = match &parent {
+            None => (0, Box::default()),
+            Some(p) => (
+                p.depth + 1,
+                p.position.iter().copied().chain([index]).collect(),
+            ),
+        };
+        Self {
+            parent,
+            name,
+            depth,
+            position,
+            handle,
+            outstanding: AtomicUsize::new(1),
+            exhausted: AtomicBool::new(false),
+            next_index: AtomicU32::new(0),
+            kept: Mutex::default(),
+            incomplete: AtomicBool::new(false),
+        }
+    }
+
+    /// Leave the entry `name` in place, and so this directory.
+    fn keep(&self, name: OsString) {
+        self.kept.lock().unwrap().insert(name);
+        self.incomplete.store(true, Ordering::Release);
+    }
+
+    /// The names from the root down to this directory.
+    fn names(&self) -> Vec<&OsStr> {
+        let mut names = Vec::with_capacity(self.depth);
+        let mut dir = self;
+        while let Some(parent) = &dir.parent {
+            names.push(dir.name.as_os_str());
+            dir = parent;
+        }
+        names.reverse();
+        names
+    }
+}
+
+enum Job {
+    /// Read a batch of names from the directory.
+    Scan(Arc<Dir>),
+    /// Remove an entry of the directory, descending into it if needed.
+    Remove(Arc<Dir>, u32, DirEntry),
+    /// Close the emptied directory and remove it from its parent.
+    Rmdir(Arc<Dir>),
+}
+
+impl Job {
+    fn dir(&self) -> &Dir {
+        match self {
+            Job::Scan(dir) | Job::Remove(dir, ..) | Job::Rmdir(dir) => dir,
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match self {
+            Job::Remove(dir, ..) => dir.depth + 1,
+            _ => self.dir().depth,
+        }
+    }
+
+    fn index(&self) -> Option<u32> {
+        match self {
+            Job::Remove(_, index, _) => Some(*index),
+            _ => None,
+        }
+    }
+}
+
+impl Ord for Job {
+    /// Greater is more important: deeper, then lexicographically earlier.
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.depth()
+            .cmp(&other.depth())
+            .then_with(|| other.dir().position.cmp(&self.dir().position))
+            .then_with(|| other.index().cmp(&self.index()))
+    }
+}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Job {}
+
+#[derive(Default)]
+struct Queues {
+    scans: BinaryHeap<Job>,
+    rmdirs: BinaryHeap<Job>,
+    removes: BinaryHeap<Job>,
+    /// Workers currently running a job.
+    active: usize,
+    /// Workers allowed to run a job at once.
+    limit: usize,
+    /// Workers started.
+    workers: usize,
+}
+
+impl Queues {
+    fn push(&mut self, job: Job) {
+        match job {
+            Job::Scan(_) => self.scans.push(job),
+            Job::Remove(..) => self.removes.push(job),
+            Job::Rmdir(_) => self.rmdirs.push(job),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Job> {
+        if self.removes.len() < LOW_WATER {
+            if let Some(job) = self.scans.pop() {
+                return Some(job);
+            }
+        }
+        self.rmdirs
+            .pop()
+            .or_else(|| self.removes.pop())
+            .or_else(|| self.scans.pop())
+    }
+
+    fn clear(&mut self) {
+        self.scans.clear();
+        self.rmdirs.clear();
+        self.removes.clear();
+    }
+}
+
+/// State shared by all the workers of one removal.
+struct Scheduler<'a> {
+    debug_root: &'a Path,
+    remover: &'a Remover,
+    outcome: &'a Outcome,
+    queues: Mutex<Queues>,
+    /// Signalled when jobs are queued, when the limit on jobs running is
+    /// raised, or when the last job finishes.
+    ready: Condvar,
+    /// Tunes that limit, in adaptive mode.
+    tuner: Option<Tuner>,
+    dirs_in_flight: AtomicUsize,
+    /// Set once any job has failed; later jobs then do nothing.
+    failed: AtomicBool,
+    error: Mutex<Option<RemoveError>>,
+}
+
+pub(super) fn remove_dir_contents<I: Io>(
+    root: File,
+    debug_root: &Path,
+    remover: &Remover,
+    outcome: &Outcome,
+) -> Result<bool, RemoveError> {
+    let scheduler = Scheduler {
+        debug_root,
+        remover,
+        outcome,
+        queues: Mutex::default(),
+        ready: Condvar::new(),
+        tuner: remover.adaptive.then(Tuner::new),
+        dirs_in_flight: AtomicUsize::new(1),
+        failed: AtomicBool::new(false),
+        error: Mutex::new(None),
+    };
+    let root = Arc::new(Dir::new(None, 0, OsString::new(), root));
+    scheduler.push(Job::Scan(root.clone()));
+    match remover.parallel_mode() {
+        ParallelMode::Serial => unreachable!("serial removal has no scheduler"),
+        #[cfg(feature = "parallel")]
+        ParallelMode::Parallel => run_on_rayon::<I>(&scheduler)?,
+        #[cfg(feature = "threads")]
+        ParallelMode::Threads(threads) => run_on_threads::<I>(&scheduler, threads),
+    }
+    match scheduler.error.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(!root.incomplete.load(Ordering::Acquire)),
+    }
+}
+
+/// The number of workers to run given `threads` threads.
+fn workers(threads: usize, remover: &Remover) -> usize {
+    match remover.max_in_flight {
+        Some(max) => threads.min(max),
+        None => threads,
+    }
+}
+
+/// Run the workers on the remover's rayon pool.
+#[cfg(feature = "parallel")]
+fn run_on_rayon<I: Io>(scheduler: &Scheduler<'_>) -> Result<(), RemoveError> {
+    let run = || {
+        rayon::scope(|s| {
+            let workers = workers(rayon::current_num_threads(), scheduler.remover);
+            for _ in 0..scheduler.start(workers, true) {
+                s.spawn(move |s| rayon_worker::<I>(scheduler, s));
+            }
+        })
+    };
+    match &scheduler.remover.thread_pool {
+        ThreadPool::Global => run(),
+        ThreadPool::Supplied(pool) => pool.install(run),
+        ThreadPool::Dedicated(threads) => rayon::ThreadPoolBuilder::new()
+            .num_threads(*threads)
+            .build()
+            .map_err(|e| {
+                let e = std::io::Error::other(e);
+                super::root_error::<I>(crate::Operation::Spawn, scheduler.debug_root, e)
+            })?
+            .install(run),
+    }
+    Ok(())
+}
+
+/// Work on the pool, starting more workers in `s` as the limit rises: a
+/// worker blocks its pool thread while it waits for jobs, so workers beyond
+/// the limit would only keep the pool from other work.
+#[cfg(feature = "parallel")]
+fn rayon_worker<'s, I: Io>(scheduler: &'s Scheduler<'s>, s: &rayon::Scope<'s>) {
+    scheduler.work::<I>(&|| s.spawn(move |s| rayon_worker::<I>(scheduler, s)));
+}
+
+/// Run the workers on `threads` scoped threads, one of them the calling
+/// thread; `0` means one per CPU.
+#[cfg(feature = "threads")]
+fn run_on_threads<I: Io>(scheduler: &Scheduler<'_>, threads: usize) {
+    let threads = match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    std::thread::scope(|s| {
+        for _ in 1..scheduler.start(workers(threads, scheduler.remover), false) {
+            let spawned = std::thread::Builder::new()
+                .name("remove_dir_all".into())
+                .spawn_scoped(s, || scheduler.work::<I>(&|| ()));
+            if let Err(_e) = spawned {
+                // Carry on with the workers already running
+                #[cfg(feature = "log")]
+                log::debug!("could not start a deletion thread: {}", _e);
+                break;
+            }
+        }
+        scheduler.work::<I>(&|| ());
+    });
+}
+
+impl Scheduler<'_> {
+    /// Set the limit on jobs running at once for `workers` workers: all of
+    /// them, or in adaptive mode one to begin with. Returns the workers to
+    /// start: all of them, or `on_demand` only as many as the limit, the rest
+    /// being started by [`Self::work`] as it rises.
+    fn start(&self, workers: usize, on_demand: bool) -> usize {
+        let limit = match &self.tuner {
+            Some(tuner) => tuner.start(workers, Instant::now()),
+            None => workers,
+        };
+        let mut queues = self.queues.lock().unwrap();
+        queues.limit = limit;
+        queues.workers = if on_demand { limit } else { workers };
+        queues.workers
+    }
+
+    /// Run `op`, an IO operation, feeding its latency to the tuner if any.
+    fn timed<T>(&self, op: impl FnOnce() -> T) -> T {
+        let Some(tuner) = &self.tuner else {
+            return op();
+        };
+        let started = Instant::now();
+        let result = op();
+        let now = Instant::now();
+        if let Some(limit) = tuner.record(now - started, now) {
+            self.queues.lock().unwrap().limit = limit;
+            self.ready.notify_all();
+        }
+        result
+    }
+
+    fn push(&self, job: Job) {
+        self.queues.lock().unwrap().push(job);
+        self.ready.notify_one();
+    }
+
+    fn fail(&self, e: RemoveError) {
+        let mut error = self.error.lock().unwrap();
+        if error.is_none() {
+            *error = Some(e);
+        }
+        self.failed.store(true, Ordering::Release);
+        self.queues.lock().unwrap().clear();
+    }
+
+    fn failed(&self) -> bool {
+        self.failed.load(Ordering::Acquire)
+    }
+
+    /// Display the path of `leaf` within the directory named by `names`.
+    fn path_of<'a>(
+        &'a self,
+        names: &'a [&'a OsStr],
+        leaf: &'a OsStr,
+    ) -> PathComponents<'a, impl Iterator<Item = &'a OsStr> + Clone> {
+        PathComponents::new(self.debug_root, names.iter().copied().chain(Some(leaf)))
+    }
+
+    /// Run jobs until there are none left and no other worker is running one,
+    /// calling `start_worker` to start another whenever the limit rises above
+    /// the workers started.
+    fn work<I: Io>(&self, start_worker: &dyn Fn()) {
+        let mut queues = self.queues.lock().unwrap();
+        loop {
+            while queues.workers < queues.limit {
+                queues.workers += 1;
+                start_worker();
+            }
+            let job = if queues.active < queues.limit {
+                queues.pop()
+            } else {
+                None
+            };
+            if let Some(job) = job {
+                queues.active += 1;
+                drop(queues);
+                if let Err(e) = self.run::<I>(job) {
+                    self.fail(e);
+                }
+                queues = self.queues.lock().unwrap();
+                queues.active -= 1;
+                continue;
+            }
+            if queues.active == 0 {
+                // Nothing queued and nothing running that could queue more;
+                // with none running the limit didn't stop us popping a job
+                self.ready.notify_all();
+                return;
+            }
+            queues = self.ready.wait(queues).unwrap();
+        }
+    }
+
+    fn run<I: Io>(&self, job: Job) -> Result<(), RemoveError> {
+        if self.failed() {
+            return Ok(());
+        }
+        match job {
+            Job::Scan(dir) => self.scan::<I>(dir),
+            Job::Remove(dir, index, name) => self.remove::<I>(dir, index, name),
+            Job::Rmdir(dir) => self.rmdir::<I>(dir),
+        }
+    }
+
+    /// Read a batch of entries from `dir` and queue their removal.
+    fn scan<I: Io>(&self, dir: Arc<Dir>) -> Result<(), RemoveError> {
+        let (entries, exhausted) = {
+            let names = dir.names();
+            let path = PathComponents::new(self.debug_root, names.iter().copied());
+            #[cfg(feature = "log")]
+            log::trace!("scanning {}", path);
+            // The jobs removing the entries of the last batch have all
+            // finished, so nothing else wants the names kept.
+            let kept = dir.kept.lock().unwrap();
+            let scanned = self
+                .timed(|| scan_batch::<I>(&dir.handle, &path, self.remover, &kept, self.outcome));
+            match scanned {
+                Ok(scanned) => scanned,
+                Err(e) => {
+                    // Leave the directory, and whatever is still in it
+                    self.outcome.absorb(e)?;
+                    dir.incomplete.store(true, Ordering::Release);
+                    (Vec::new(), true)
+                }
+            }
+        };
+        dir.exhausted.store(exhausted, Ordering::Release);
+        dir.outstanding.fetch_add(entries.len(), Ordering::AcqRel);
+        let first = dir
+            .next_index
+            .fetch_add(entries.len() as u32, Ordering::Relaxed);
+        {
+            let mut queues = self.queues.lock().unwrap();
+            for (index, entry) in (first..).zip(entries) {
+                queues.push(Job::Remove(dir.clone(), index, entry));
+            }
+        }
+        self.ready.notify_all();
+        self.finish_one(dir);
+        Ok(())
+    }
+
+    /// Remove `entry` of `dir`, descending into it if it is a directory.
+    fn remove<I: Io>(&self, dir: Arc<Dir>, index: u32, entry: DirEntry) -> Result<(), RemoveError> {
+        let DirEntry { name, kind, .. } = entry;
+        let child = {
+            let names = dir.names();
+            let path = self.path_of(&names, &name);
+            self.timed(|| remove_entry::<I>(&dir.handle, &name, kind, &path, self.outcome))
+        };
+        let child = match child {
+            Ok(Removal::Dir(child)) => child,
+            Ok(Removal::Removed(_)) => {
+                self.finish_one(dir);
+                return Ok(());
+            }
+            Ok(Removal::Skipped) => {
+                dir.keep(name);
+                self.finish_one(dir);
+                return Ok(());
+            }
+            Err(e) => {
+                self.outcome.absorb(e)?;
+                dir.keep(name);
+                self.finish_one(dir);
+                return Ok(());
+            }
+        };
+        if dir.depth + 1 < MAX_PARALLEL_DEPTH && self.reserve_dir() {
+            self.push(Job::Scan(Arc::new(Dir::new(Some(dir), index, name, child))));
+            return Ok(());
+        }
+        // Finish this subtree serially from this job, keeping the descriptors
+        // it needs bounded.
+        let removed = {
+            let names = dir.names();
+            let path = self.path_of(&names, &name);
+            let child = remove_dir_contents_serial::<I>(
+                child,
+                &path.to_path_buf(),
+                self.remover,
+                self.outcome,
+            )?;
+            self.remove_empty_dir::<I>(&dir, &name, child.is_some(), child, &path)?
+        };
+        if !removed {
+            dir.keep(name);
+        }
+        self.finish_one(dir);
+        Ok(())
+    }
+
+    /// Remove the directory `name` of `parent`, with its `handle` if still
+    /// open, if it was `emptied`. Returns whether it was removed: in
+    /// keep-going mode it is left in place when not emptied, or on failure.
+    fn remove_empty_dir<I: Io>(
+        &self,
+        parent: &Dir,
+        name: &OsStr,
+        emptied: bool,
+        handle: Option<File>,
+        path: &dyn LazyPath,
+    ) -> Result<bool, RemoveError> {
+        if !emptied {
+            self.outcome.leave(path.to_path_buf());
+            return Ok(false);
+        }
+        match remove_empty_dir::<I>(&parent.handle, name, handle, path, self.outcome) {
+            Ok(_) => Ok(true),
+            Err(e) => self.outcome.absorb(e).map(|()| false),
+        }
+    }
+
+    /// Count one more directory in flight, unless there are enough already.
+    fn reserve_dir(&self) -> bool {
+        self.dirs_in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < MAX_DIRS_IN_FLIGHT).then_some(n + 1)
+            })
+            .is_ok()
+    }
+
+    fn rmdir<I: Io>(&self, dir: Arc<Dir>) -> Result<(), RemoveError> {
+        let parent = dir.parent.clone().expect("the root is never removed");
+        let name = dir.name.clone();
+        let emptied = !dir.incomplete.load(Ordering::Acquire);
+        // Our reference is normally the last one, so this closes the handle:
+        // nothing more is needed from it.
+        drop(dir);
+        let removed = {
+            let names = parent.names();
+            let path = self.path_of(&names, &name);
+            self.timed(|| self.remove_empty_dir::<I>(&parent, &name, emptied, None, &path))?
+        };
+        if !removed {
+            parent.keep(name);
+        }
+        self.dirs_in_flight.fetch_sub(1, Ordering::AcqRel);
+        self.finish_one(parent);
+        Ok(())
+    }
+
+    /// Record that one piece of work on `dir` has completed. Once all of them
+    /// have, the directory is either scanned again or, if the last scan reached
+    /// its end, queued for removal.
+    fn finish_one(&self, dir: Arc<Dir>) {
+        if dir.outstanding.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        if !dir.exhausted.load(Ordering::Acquire) {
+            dir.outstanding.store(1, Ordering::Release);
+            self.push(Job::Scan(dir));
+        } else if dir.parent.is_some() {
+            self.push(Job::Rmdir(dir));
+        }
+        // Otherwise the root has been emptied
+    }
+}
@@ -0,0 +1,171 @@
+//! Adaptive concurrency: the number of operations parallel deletion keeps in
+//! flight is tuned from the latency and throughput observed while it runs.
+//!
+//! Deletion starts with one operation in flight. Time is cut into windows of
+//! at least [`WINDOW`] and [`MIN_WINDOW_OPS`] operations, and at the end of
+//! each:
+//! - if operations were fast, averaging under [`FAST_OP`], the filesystem is
+//!   local or cached and more concurrency only adds contention: halve it.
+//! - if concurrency was just raised and throughput did not improve by
+//!   [`MIN_GAIN`], the raise didn't pay off (APFS's global lock, say): go back,
+//!   and hold there for [`HOLD_WINDOWS`] windows before probing again.
+//! - otherwise double it, up to the number of workers.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const WINDOW: Duration = Duration::from_millis(10);
+
+const MIN_WINDOW_OPS: u32 = 16;
+
+/// Operations faster than this on average gain nothing from concurrency.
+const FAST_OP: Duration = Duration::from_micros(20);
+
+/// Throughput must improve by this factor to justify more concurrency.
+const MIN_GAIN: f64 = 1.1;
+
+const HOLD_WINDOWS: u32 = 8;
+
+pub(super) struct Tuner {
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Workers available, so the highest useful limit.
+    max: usize,
+    limit: usize,
+    window_start: Instant,
+    ops: u32,
+    /// Total latency of the operations in this window.
+    busy: Duration,
+    /// Limit and throughput of the previous window.
+    previous: Option<(usize, f64)>,
+    hold: u32,
+}
+
+impl Tuner {
+    pub(super) fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                max: 1,
+                limit: 1,
+                window_start: Instant::now(),
+                ops: 0,
+                busy: Duration::ZERO,
+                previous: None,
+                hold: 0,
+            }),
+        }
+    }
+
+    /// Start tuning with `max` workers available; returns the initial limit.
+    pub(super) fn start(&self, max: usize, now: Instant) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.max = max.max(1);
+        state.window_start = now;
+        state.limit
+    }
+
+    /// Record an operation that took `latency` and completed at `now`.
+    /// Returns the new limit when a window ends with it changed.
+    pub(super) fn record(&self, latency: Duration, now: Instant) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        state.ops += 1;
+        state.busy += latency;
+        let elapsed = now.saturating_duration_since(state.window_start);
+        if elapsed < WINDOW || state.ops < MIN_WINDOW_OPS {
+            return None;
+        }
+        let throughput = f64::from(state.ops) / elapsed.as_secs_f64();
+        let limit = state.limit;
+        let next = if state.busy / state.ops < FAST_OP {
+            (limit / 2).max(1)
+        } else if state.hold > 0 {
+            state.hold -= 1;
+            limit
+        } else {
+            match state.previous {
+                Some((previous, previous_throughput))
+                    if limit > previous && throughput < previous_throughput * MIN_GAIN =>
+                {
+                    state.hold = HOLD_WINDOWS;
+                    previous
+                }
+                _ => (limit * 2).min(state.max),
+            }
+        };
+        #[cfg(feature = "log")]
+        if next != limit {
+            log::debug!(
+                "{} operations in flight: {:.0}/s, mean latency {:?}",
+                next,
+                throughput,
+                state.busy / state.ops
+            );
+        }
+        state.previous = Some((limit, throughput));
+        state.limit = next;
+        state.window_start = now;
+        state.ops = 0;
+        state.busy = Duration::ZERO;
+        (next != limit).then_some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Tuner;
+
+    const MAX: usize = 16;
+
+    /// Drive a tuner for a simulated second of operations that each take
+    /// `latency(limit)`, and with `limit` in flight complete at
+    /// `throughput(limit)` per second. Returns the limit at the end.
+    fn settle(latency: impl Fn(usize) -> Duration, throughput: impl Fn(usize) -> f64) -> usize {
+        let tuner = Tuner::new();
+        let mut now = Instant::now();
+        let end = now + Duration::from_secs(1);
+        let mut limit = tuner.start(MAX, now);
+        while now < end {
+            now += Duration::from_secs_f64(1.0 / throughput(limit));
+            if let Some(next) = tuner.record(latency(limit), now) {
+                limit = next;
+            }
+        }
+        limit
+    }
+
+    #[test]
+    fn fast_operations_stay_serial() {
+        let latency = Duration::from_micros(3);
+        assert_eq!(
+            1,
+            settle(|_| latency, |limit| limit as f64 / latency.as_secs_f64())
+        );
+    }
+
+    #[test]
+    fn slow_operations_grow_to_the_workers_available() {
+        // A network filesystem: latency is flat, so throughput scales
+        let latency = Duration::from_millis(1);
+        assert_eq!(
+            MAX,
+            settle(|_| latency, |limit| limit as f64 / latency.as_secs_f64())
+        );
+    }
+
+    #[test]
+    fn contended_operations_back_off() {
+        // A global lock: throughput is flat, so latency grows with the limit
+        let throughput = 2_000.0;
+        let limit = settle(
+            |limit| Duration::from_secs_f64(limit as f64 / throughput),
+            |_| throughput,
+        );
+        assert!(limit <= 2, "{limit}");
+    }
+}
@@ -1,22 +1,77 @@
-use std::{fmt::Display, path::Path};
+use std::{
+    ffi::OsStr,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
 
-/// Print a path that is broken into segments.
-// explicitly typed to avoid type recursion. 'a is the smallest lifetime present
-// : that of the child.
-pub(crate) enum PathComponents<'a> {
-    Path(&'a Path),
-    Component(&'a PathComponents<'a>, &'a Path),
+/// A path that is only put together when it is needed: displayed in a log
+/// message, or built in full for an error.
+pub(crate) trait LazyPath: Display {
+    fn to_path_buf(&self) -> PathBuf;
 }
 
-impl<'p> Display for PathComponents<'p> {
+/// Print a path that is broken into segments: a root, and the names leading
+/// from it. The names are borrowed from wherever the traversal keeps them, so
+/// building one costs nothing unless it is actually displayed.
+pub(crate) struct PathComponents<'a, C> {
+    root: &'a Path,
+    components: C,
+}
+
+impl<'a, C> PathComponents<'a, C>
+where
+    C: Iterator<Item = &'a OsStr> + Clone,
+{
+    pub(crate) fn new(root: &'a Path, components: C) -> Self {
+        Self { root, components }
+    }
+}
+
+impl<'a, C> Display for PathComponents<'a, C>
+where
+    C: Iterator<Item = &'a OsStr> + Clone,
+{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            PathComponents::Path(p) => p.display().fmt(f),
-            PathComponents::Component(p, c) => {
-                p.fmt(f)?;
-                f.write_str("/")?;
-                c.display().fmt(f)
-            }
+        self.root.display().fmt(f)?;
+        for c in self.components.clone() {
+            f.write_str("/")?;
+            Path::new(c).display().fmt(f)?;
         }
+        Ok(())
+    }
+}
+
+impl<'a, C> LazyPath for PathComponents<'a, C>
+where
+    C: Iterator<Item = &'a OsStr> + Clone,
+{
+    fn to_path_buf(&self) -> PathBuf {
+        let mut path = self.root.to_path_buf();
+        path.extend(self.components.clone());
+        path
+    }
+}
+
+/// The entry `name` of the directory at `parent`.
+pub(crate) struct Child<'a> {
+    parent: &'a dyn LazyPath,
+    name: &'a OsStr,
+}
+
+impl<'a> Child<'a> {
+    pub(crate) fn new(parent: &'a dyn LazyPath, name: &'a OsStr) -> Self {
+        Self { parent, name }
+    }
+}
+
+impl Display for Child<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.parent, Path::new(self.name).display())
+    }
+}
+
+impl LazyPath for Child<'_> {
+    fn to_path_buf(&self) -> PathBuf {
+        self.parent.to_path_buf().join(self.name)
     }
 }
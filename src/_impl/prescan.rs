@@ -0,0 +1,106 @@
+//! Walking a tree before removing it: counting it, so that progress can be
+//! measured against it, or listing it for a [`crate::DeletionPlan`].
+
+use std::{fs::File, io, path::Path};
+
+use super::{
+    dir_stack::{DirStack, Levels},
+    entry_error,
+    io::{EntryMetadata, EntryType, Io},
+    outcome::Outcome,
+    path_components::LazyPath,
+    root_error,
+};
+use crate::{Operation, RemoveError, Remover};
+
+/// What a pre-scan found.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Totals {
+    pub(crate) entries: u64,
+    pub(crate) bytes: u64,
+}
+
+impl Totals {
+    pub(crate) fn add(&mut self, usage: EntryMetadata) {
+        self.entries += 1;
+        self.bytes += usage.bytes_freed();
+    }
+}
+
+/// Count the entries under `d`, and the bytes removing them would free,
+/// leaving out the mount points `remover` doesn't cross.
+pub(crate) fn count<I: Io>(d: &File, remover: &Remover) -> io::Result<Totals> {
+    let root = Path::new("");
+    let mut outcome = Outcome::new(remover, root);
+    outcome.one_file_system(d, remover)?;
+    let mut totals = Totals::default();
+    walk::<I>(d, root, remover, &outcome, |_, metadata| {
+        totals.add(metadata)
+    })
+    .map_err(RemoveError::into_io_error)?;
+    Ok(totals)
+}
+
+/// Call `visit` with the path and metadata of each entry under `d`, which is
+/// at `root`, parents before their children. Mount points that `outcome`
+/// keeps removal out of are left out, with everything under them.
+///
+/// The tree is walked as removal walks it, through a [`DirStack`]:
+/// directories are opened by name from their parent without following
+/// symlinks and read a batch at a time, and only some levels keep their
+/// handle, so neither memory nor descriptors grow with the size of the tree.
+pub(crate) fn walk<I: Io>(
+    d: &File,
+    root: &Path,
+    remover: &Remover,
+    outcome: &Outcome,
+    mut visit: impl FnMut(&Path, EntryMetadata),
+) -> Result<(), RemoveError> {
+    let d = I::duplicate_fd(d).map_err(|e| root_error::<I>(Operation::Open, root, e))?;
+    let mut levels = Levels::new(d);
+    let mut stack = DirStack::new(&mut levels, root, remover, outcome);
+    loop {
+        let Some(entry) = stack.next_listed::<I>()? else {
+            if stack.is_root() {
+                return Ok(());
+            }
+            stack.pop();
+            continue;
+        };
+        let listed = stack.retry_starved::<I, _, _>(|stack| {
+            stack.ensure_top_open::<I>()?;
+            let parent = stack.top_handle();
+            let path = stack.path_of(&entry.name);
+            let fail = |operation, e| entry_error::<I>(operation, parent, &entry.name, &path, e);
+            cfg_if::cfg_if! {
+                if #[cfg(windows)] {
+                    let child =
+                        I::open_dir_at(parent, &entry.name).map_err(|e| fail(Operation::Open, e))?;
+                    let metadata = I::metadata(&child).map_err(|e| fail(Operation::Stat, e))?;
+                    let child = (metadata.file_type == EntryType::Dir).then_some(child);
+                } else {
+                    let metadata = I::metadata_at(parent, &entry.name)
+                        .map_err(|e| fail(Operation::Stat, e))?;
+                    if metadata.file_type != EntryType::Dir {
+                        return Ok(Some((metadata, None)));
+                    }
+                    let child =
+                        I::open_dir_at(parent, &entry.name).map_err(|e| fail(Operation::Open, e))?;
+                    let opened = child.metadata().map_err(|e| fail(Operation::Stat, e))?;
+                    if !outcome.within_file_system(&path, &opened)? {
+                        return Ok(None);
+                    }
+                    let child = Some(child);
+                }
+            }
+            Ok(Some((metadata, child)))
+        })?;
+        let Some((metadata, child)) = listed else {
+            continue;
+        };
+        visit(&stack.path_of(&entry.name).to_path_buf(), metadata);
+        if let Some(child) = child {
+            stack.push(entry.name, child);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Resolving the paths given to a remover a name at a time, without following
+//! symlinks, for [`crate::RemoverBuilder::strict_paths`] and
+//! [`crate::RemoverBuilder::beneath`].
+
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use super::{
+    io::{EntryType, Io},
+    root_error,
+};
+use crate::{Operation, RemoveError, RemoveErrorKind};
+
+/// How a remover resolves the paths it is given.
+#[derive(Debug, Clone, Default)]
+pub(crate) enum Resolution {
+    /// Normalised, following symlinks, and opened whole.
+    #[default]
+    Normalize,
+    /// A name at a time from the current or root directory, refusing
+    /// symlinks along the way, and `..` after a name.
+    Strict,
+    /// A name at a time from a base directory, refusing symlinks along the way
+    /// and paths that would leave it.
+    Beneath(Arc<File>),
+}
+
+impl Resolution {
+    /// Whether paths are normalised, and then opened whole.
+    pub(crate) fn normalizes(&self) -> bool {
+        matches!(self, Resolution::Normalize)
+    }
+
+    /// Open the directory at `path`, or `None` if paths are opened whole.
+    pub(crate) fn open_dir<I: Io>(&self, path: &Path) -> Option<Result<File, RemoveError>> {
+        if self.normalizes() {
+            return None;
+        }
+        Some(self.walk::<I>(path).and_then(|walk| walk.open::<I>(path)))
+    }
+
+    /// Open the parent of the entry at `path`, returning it with the name of
+    /// the entry in it, or `None` if paths are opened whole.
+    pub(crate) fn open_parent<'p, I: Io>(
+        &self,
+        path: &'p Path,
+    ) -> Option<Result<(File, &'p OsStr), RemoveError>> {
+        if self.normalizes() {
+            return None;
+        }
+        Some(self.walk::<I>(path).and_then(|mut walk| {
+            let name = walk.names.pop().ok_or_else(|| {
+                let e = io::Error::new(io::ErrorKind::InvalidInput, "names no entry to remove");
+                RemoveError::new(path.into(), Operation::Open, walk.refused, e)
+            })?;
+            Ok((walk.open::<I>(path)?, name))
+        }))
+    }
+
+    /// Where resolving `path` starts, and the names to open from there.
+    fn walk<'p, I: Io>(&self, path: &'p Path) -> Result<Walk<'p>, RemoveError> {
+        let mut names = Vec::new();
+        match self {
+            Resolution::Beneath(base) => {
+                for component in path.components() {
+                    match component {
+                        Component::CurDir => (),
+                        Component::Normal(name) => names.push(name),
+                        Component::RootDir | Component::Prefix(_) | Component::ParentDir => {
+                            let e = io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                "leaves the base directory",
+                            );
+                            let kind = RemoveErrorKind::NotBeneath;
+                            return Err(RemoveError::new(path.into(), Operation::Open, kind, e));
+                        }
+                    }
+                }
+                let start =
+                    I::duplicate_fd(base).map_err(|e| root_error::<I>(Operation::Open, path, e))?;
+                Ok(Walk {
+                    start,
+                    names,
+                    refused: RemoveErrorKind::NotBeneath,
+                })
+            }
+            _ => {
+                // `..` after a name goes wherever that name leads, which is
+                // not back where it started if the name is a symlink, so is
+                // refused; leading ones, like the root, can't be symlinks, so
+                // are opened with the start
+                let mut start = PathBuf::new();
+                for component in path.components() {
+                    match component {
+                        Component::CurDir => (),
+                        Component::Normal(name) => names.push(name),
+                        Component::ParentDir if !names.is_empty() => {
+                            let e = io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                "`..` after a name, which may be a symlink",
+                            );
+                            let kind = RemoveErrorKind::Other;
+                            return Err(RemoveError::new(path.into(), Operation::Open, kind, e));
+                        }
+                        other => start.push(other),
+                    }
+                }
+                if start.as_os_str().is_empty() {
+                    start.push(Component::CurDir);
+                }
+                let start =
+                    I::open_dir(&start).map_err(|e| root_error::<I>(Operation::Open, path, e))?;
+                Ok(Walk {
+                    start,
+                    names,
+                    refused: RemoveErrorKind::SymlinkInPath,
+                })
+            }
+        }
+    }
+}
+
+/// The names along a path, to be opened one after another from a start.
+struct Walk<'p> {
+    start: File,
+    names: Vec<&'p OsStr>,
+    /// The kind of error a symlink along the path is refused with.
+    refused: RemoveErrorKind,
+}
+
+impl Walk<'_> {
+    /// Open the directory the names lead to, at `path`, without following
+    /// symlinks anywhere along them.
+    fn open<I: Io>(self, path: &Path) -> Result<File, RemoveError> {
+        let fail = |e| root_error::<I>(Operation::Open, path, e);
+        let refuse = |e| RemoveError::new(path.into(), Operation::Open, self.refused, e);
+        if self.names.is_empty() {
+            return Ok(self.start);
+        }
+        let relative: PathBuf = self.names.iter().collect();
+        if let Some(result) = I::open_dir_beneath(&self.start, &relative) {
+            return result.map_err(|e| match e.raw_os_error() {
+                // A symlink along the path, or a way out of the start
+                Some(libc::ELOOP | libc::EXDEV) => refuse(e),
+                _ => fail(e),
+            });
+        }
+        let mut dir = self.start;
+        for name in &self.names {
+            dir = I::open_dir_at(&dir, name).map_err(|e| match I::metadata_at(&dir, name) {
+                Ok(metadata) if metadata.file_type == EntryType::Symlink => refuse(e),
+                _ => fail(e),
+            })?;
+        }
+        Ok(dir)
+    }
+}
@@ -0,0 +1,169 @@
+//! Serial removal driven one entry at a time, for [`crate::RemoveIter`].
+
+use std::{marker::PhantomData, path::PathBuf};
+
+use super::{
+    dir_stack::{DirStack, Levels},
+    io::{EntryMetadata, Io},
+    outcome::{Outcome, Phase},
+    path_components::PathComponents,
+    read_metadata, root_error, serial_step, RootEntry,
+};
+use crate::{Operation, RemoveError, Remover};
+
+/// Names read per directory scan. A step may scan, so this bounds the work of
+/// one step, where removing a whole tree reads thousands at once to batch or
+/// sort them.
+pub(crate) const STEP_SCAN_BATCH: usize = 64;
+
+/// The removal of the tree at a path, made a step at a time as entries are
+/// asked for.
+pub(crate) struct Steps<I: Io> {
+    root: PathBuf,
+    remover: Remover,
+    outcome: Outcome,
+    state: State<I>,
+    _io: PhantomData<I>,
+}
+
+enum State<I: Io> {
+    /// The root is yet to be opened.
+    Start,
+    /// Emptying the root, whose metadata was read on opening it.
+    Contents {
+        levels: Levels,
+        root: RootEntry<I>,
+        metadata: Option<EntryMetadata>,
+    },
+    /// The root is emptied, unless entries were left in it in keep-going
+    /// mode, and is to be removed.
+    Root {
+        emptied: bool,
+        root: RootEntry<I>,
+        metadata: Option<EntryMetadata>,
+    },
+    Finished,
+}
+
+impl<I: Io> Steps<I> {
+    pub(crate) fn new(root: PathBuf, remover: &Remover) -> Self {
+        // The metadata of each entry removed is given out
+        let mut remover = remover.clone();
+        remover.stats = true;
+        remover.scan_batch = Some(STEP_SCAN_BATCH);
+        remover.inode_order = None;
+        let outcome = Outcome::new(&remover, &root).one_at_a_time();
+        Self {
+            root,
+            remover,
+            outcome,
+            state: State::Start,
+            _io: PhantomData,
+        }
+    }
+
+    /// Take a step, returning the entry it removed, if any.
+    fn step(&mut self) -> Result<Option<(PathBuf, EntryMetadata)>, RemoveError> {
+        match std::mem::replace(&mut self.state, State::Finished) {
+            State::Start => {
+                self.root = self.remover.normalize_root(&self.root)?;
+                // Hooks are given paths relative to the root as normalised
+                self.outcome = Outcome::new(&self.remover, &self.root).one_at_a_time();
+                let (d, root) = RootEntry::open(&self.root, &self.remover)?;
+                let metadata = read_metadata(&self.outcome, || I::metadata(&d))
+                    .map_err(|e| root_error::<I>(Operation::Stat, &self.root, e))?;
+                self.outcome
+                    .one_file_system(&d, &self.remover)
+                    .map_err(|e| root_error::<I>(Operation::Stat, &self.root, e))?;
+                self.outcome.prescan::<I>(&d, metadata, &self.remover);
+                self.state = State::Contents {
+                    levels: Levels::new(d),
+                    root,
+                    metadata,
+                };
+                Ok(None)
+            }
+            State::Contents {
+                mut levels,
+                root,
+                metadata,
+            } => {
+                let mut removed = None;
+                let more = {
+                    let mut stack =
+                        DirStack::new(&mut levels, &self.root, &self.remover, &self.outcome);
+                    serial_step::<I>(&mut stack, &self.outcome, |path, metadata| {
+                        removed = Some((path.to_path_buf(), metadata));
+                    })?
+                };
+                self.state = if more {
+                    State::Contents {
+                        levels,
+                        root,
+                        metadata,
+                    }
+                } else {
+                    State::Root {
+                        emptied: levels.into_root().is_some(),
+                        root,
+                        metadata,
+                    }
+                };
+                Ok(removed.map(|(path, metadata)| (path, expect_read(metadata))))
+            }
+            State::Root {
+                emptied,
+                root,
+                metadata,
+            } => {
+                if !emptied {
+                    self.outcome.leave(self.root.clone());
+                    return Ok(None);
+                }
+                let path = PathComponents::new(&self.root, std::iter::empty());
+                self.outcome.checkpoint(&path, Operation::Rmdir)?;
+                let timer = self.outcome.timer(Phase::Rmdir);
+                let result = root.remove(&self.root);
+                drop(timer);
+                match result {
+                    Ok(()) => {
+                        #[cfg(feature = "log")]
+                        log::trace!("removed {}", self.root.display());
+                        let metadata = expect_read(metadata);
+                        self.outcome.removed(metadata);
+                        Ok(Some((self.root.clone(), metadata)))
+                    }
+                    Err(e) => self.outcome.absorb(e).map(|()| None),
+                }
+            }
+            State::Finished => Ok(None),
+        }
+    }
+}
+
+impl<I: Io> Iterator for Steps<I> {
+    type Item = Result<(PathBuf, EntryMetadata), RemoveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // Failures collected in keep-going mode come out in order
+            if let Some(e) = self.outcome.take_error() {
+                return Some(Err(e));
+            }
+            if matches!(self.state, State::Finished) {
+                return None;
+            }
+            match self.step() {
+                Ok(Some(removed)) => return Some(Ok(removed)),
+                Ok(None) => (),
+                // The state is left finished
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Metadata read for statistics, which are always collected here.
+fn expect_read(metadata: Option<EntryMetadata>) -> EntryMetadata {
+    metadata.expect("metadata is read with statistics")
+}
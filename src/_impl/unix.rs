@@ -1,21 +1,37 @@
+use std::ffi::{CString, OsStr};
 use std::fs::{File, OpenOptions};
 use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::OpenOptionsExt;
-use std::os::unix::prelude::FromRawFd;
+use std::os::unix::prelude::{FromRawFd, RawFd};
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{fs, os::unix::prelude::AsRawFd};
 
 use cvt::cvt;
 use libc::{self, fcntl, F_DUPFD_CLOEXEC};
+use nix::dir::{Dir, Type};
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd"
+))]
+use nix::sys::statfs::fstatfs;
 
-use super::io::Io;
+use super::io::{DirEntry, DirPosition, EntryKind, EntryMetadata, EntryType, Io};
+use crate::{Filesystem, RemoveErrorKind};
 
 pub(crate) struct UnixIo;
 
 impl Io for UnixIo {
-    type UniqueIdentifier = ();
+    /// The device and inode number.
+    type UniqueIdentifier = (u64, u64);
 
-    fn duplicate_fd(f: &mut fs::File) -> io::Result<fs::File> {
+    fn duplicate_fd(f: &fs::File) -> io::Result<fs::File> {
         let source_fd = f.as_raw_fd();
         // F_DUPFD_CLOEXEC seems to be quite portable, but we should be prepared
         // to add in more codepaths here.
@@ -30,8 +46,126 @@ impl Io for UnixIo {
         options.open(p)
     }
 
-    fn unique_identifier(_d: &fs::File) -> io::Result<Self::UniqueIdentifier> {
-        todo!()
+    fn open_dir_at(parent: &fs::File, name: &OsStr) -> io::Result<fs::File> {
+        fs_at::OpenOptions::default()
+            .read(true)
+            .write(fs_at::OpenOptionsWriteMode::Write)
+            .follow(false)
+            .open_dir_at(parent, name)
+    }
+
+    fn read_dir(d: &fs::File, limit: usize) -> io::Result<Vec<DirEntry>> {
+        read_entries(d, 0, limit)
+    }
+
+    fn read_dir_from(
+        d: &fs::File,
+        position: &mut DirPosition,
+        limit: usize,
+    ) -> io::Result<Vec<DirEntry>> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                read_entries_from(d, position, limit)
+            } else {
+                let entries = read_entries(d, position.entries, limit)?;
+                position.entries += entries.len();
+                Ok(entries)
+            }
+        }
+    }
+
+    fn metadata(f: &fs::File) -> io::Result<EntryMetadata> {
+        Ok(metadata(&stat(f)?))
+    }
+
+    fn metadata_at(parent: &fs::File, name: &OsStr) -> io::Result<EntryMetadata> {
+        Ok(metadata(&stat_at(parent, name)?))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn open_dir_beneath(base: &fs::File, path: &Path) -> Option<io::Result<fs::File>> {
+        let path = match CString::new(path.as_os_str().as_bytes()) {
+            Ok(path) => path,
+            Err(e) => return Some(Err(e.into())),
+        };
+        // open_how is non-exhaustive, and the kernel wants unused fields zeroed
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        // RESOLVE_NO_SYMLINKS refuses a symlink at the end with ELOOP, where
+        // O_NOFOLLOW would leave O_DIRECTORY to refuse it with ENOTDIR
+        how.flags = (libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC) as u64;
+        how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_SYMLINKS;
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                base.as_raw_fd(),
+                path.as_ptr(),
+                &how as *const libc::open_how,
+                std::mem::size_of::<libc::open_how>(),
+            )
+        };
+        if fd < 0 {
+            let e = io::Error::last_os_error();
+            // ENOSYS: kernels before 5.6. EPERM: seccomp filters that
+            // predate openat2. EAGAIN: a rename raced with the lookup.
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::ENOSYS | libc::EPERM | libc::EAGAIN)
+            ) {
+                return None;
+            }
+            return Some(Err(e));
+        }
+        Some(Ok(unsafe { File::from_raw_fd(fd as RawFd) }))
+    }
+
+    fn filesystem(d: &fs::File) -> io::Result<Filesystem> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                // The magic numbers are 32 bits, though f_type is wider, and
+                // signed, on some targets
+                Ok(match fstatfs(d)?.filesystem_type().0 as u32 {
+                    0x0102_1994 => Filesystem::Tmpfs,
+                    0xEF53 => Filesystem::Ext4,
+                    0x9123_683E => Filesystem::Btrfs,
+                    0x5846_5342 => Filesystem::Xfs,
+                    0x2FC1_2FC1 => Filesystem::Zfs,
+                    0x6969 => Filesystem::Nfs,
+                    // SMB, CIFS, SMB2
+                    0x517B | 0xFF53_4D42 | 0xFE53_4D42 => Filesystem::Smb,
+                    0x6573_5546 => Filesystem::Fuse,
+                    0x794C_7630 => Filesystem::Overlay,
+                    _ => Filesystem::Other,
+                })
+            } else if #[cfg(any(
+                target_vendor = "apple",
+                target_os = "freebsd",
+                target_os = "dragonfly",
+                target_os = "openbsd"
+            ))] {
+                let stat = fstatfs(d)?;
+                Ok(match stat.filesystem_type_name() {
+                    "tmpfs" => Filesystem::Tmpfs,
+                    "zfs" => Filesystem::Zfs,
+                    "nfs" => Filesystem::Nfs,
+                    "smbfs" => Filesystem::Smb,
+                    "apfs" => Filesystem::Apfs,
+                    "hfs" => Filesystem::Hfs,
+                    name if name.starts_with("fuse") || name.ends_with("fuse") => Filesystem::Fuse,
+                    _ => Filesystem::Other,
+                })
+            } else {
+                let _ = d;
+                Ok(Filesystem::Other)
+            }
+        }
+    }
+
+    fn unique_identifier(d: &fs::File) -> io::Result<Self::UniqueIdentifier> {
+        Ok(unique_identifier(&stat(d)?))
+    }
+
+    fn unique_identifier_at(parent: &fs::File, name: &OsStr) -> io::Result<Self::UniqueIdentifier> {
+        Ok(unique_identifier(&stat_at(parent, name)?))
     }
 
     fn is_not_dir_open_error(e: &io::Error) -> bool {
@@ -56,4 +190,273 @@ impl Io for UnixIo {
             }
         }
     }
+
+    fn is_dir_unlink_error(e: &io::Error) -> bool {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                // Linux refuses to unlink directories with EISDIR; EPERM means
+                // an immutable or append-only entry, or the sticky bit
+                matches!(e.raw_os_error(), Some(libc::EISDIR))
+            } else {
+                // POSIX permits EPERM, and macOS and the BSDs use it
+                matches!(e.raw_os_error(), Some(libc::EISDIR) | Some(libc::EPERM))
+            }
+        }
+    }
+
+    fn error_kind(e: &io::Error) -> RemoveErrorKind {
+        match e.raw_os_error() {
+            // Gone since the scan, or new entries since the directory was
+            // emptied
+            Some(libc::ENOENT | libc::ENOTEMPTY | libc::EEXIST) => RemoveErrorKind::Raced,
+            Some(libc::EACCES | libc::EPERM) => RemoveErrorKind::PermissionDenied,
+            Some(libc::EBUSY | libc::ETXTBSY) => RemoveErrorKind::Busy,
+            Some(libc::ENOTDIR) => RemoveErrorKind::NotADirectory,
+            Some(libc::EROFS) => RemoveErrorKind::ReadOnlyFilesystem,
+            _ => RemoveErrorKind::Other,
+        }
+    }
+
+    fn is_immutable(parent: &fs::File, name: &OsStr) -> bool {
+        if is_flagged_immutable(parent.as_raw_fd()) {
+            return true;
+        }
+        let Ok(name) = CString::new(name.as_bytes()) else {
+            return false;
+        };
+        // Only files and directories are opened to check: opening a device
+        // can have side effects, and links and sockets can't be opened.
+        let mut stat = MaybeUninit::<libc::stat>::uninit();
+        if unsafe {
+            libc::fstatat(
+                parent.as_raw_fd(),
+                name.as_ptr(),
+                stat.as_mut_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        } != 0
+        {
+            return false;
+        }
+        let mode = unsafe { stat.assume_init() }.st_mode & libc::S_IFMT;
+        if mode != libc::S_IFREG && mode != libc::S_IFDIR {
+            return false;
+        }
+        let fd = unsafe {
+            libc::openat(
+                parent.as_raw_fd(),
+                name.as_ptr(),
+                libc::O_RDONLY | libc::O_NOFOLLOW | libc::O_NONBLOCK | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return false;
+        }
+        let entry = unsafe { File::from_raw_fd(fd) };
+        is_flagged_immutable(entry.as_raw_fd())
+    }
+
+    fn is_handle_exhaustion_error(e: &io::Error) -> bool {
+        // EMFILE: the per-process limit (RLIMIT_NOFILE) was reached.
+        // ENFILE: the system-wide limit was reached.
+        matches!(e.raw_os_error(), Some(libc::EMFILE) | Some(libc::ENFILE))
+    }
+}
+
+/// `fstat` the open file `f`.
+fn stat(f: &fs::File) -> io::Result<libc::stat> {
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    cvt(unsafe { libc::fstat(f.as_raw_fd(), stat.as_mut_ptr()) })?;
+    Ok(unsafe { stat.assume_init() })
+}
+
+/// `fstatat` the entry `name` of `parent`, without following symlinks.
+fn stat_at(parent: &fs::File, name: &OsStr) -> io::Result<libc::stat> {
+    let name = CString::new(name.as_bytes())?;
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    cvt(unsafe {
+        libc::fstatat(
+            parent.as_raw_fd(),
+            name.as_ptr(),
+            stat.as_mut_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    })?;
+    Ok(unsafe { stat.assume_init() })
+}
+
+/// Whether the open file `fd` has the immutable or append-only flag, either
+/// of which prevents unlinking it or, for a directory, its entries.
+fn is_flagged_immutable(fd: RawFd) -> bool {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            // From linux/fs.h
+            const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+            const FS_APPEND_FL: libc::c_int = 0x20;
+            // Despite its definition, the kernel reads and writes an int
+            let mut flags: libc::c_int = 0;
+            let result = unsafe { libc::ioctl(fd, libc::FS_IOC_GETFLAGS, &mut flags) };
+            result == 0 && flags & (FS_IMMUTABLE_FL | FS_APPEND_FL) != 0
+        } else if #[cfg(any(target_vendor = "apple", target_os = "freebsd", target_os = "dragonfly"))] {
+            let mask = (libc::UF_IMMUTABLE | libc::SF_IMMUTABLE | libc::UF_APPEND | libc::SF_APPEND) as u64;
+            let mut stat = MaybeUninit::<libc::stat>::uninit();
+            let result = unsafe { libc::fstat(fd, stat.as_mut_ptr()) };
+            result == 0 && u64::from(unsafe { stat.assume_init() }.st_flags) & mask != 0
+        } else {
+            let _ = fd;
+            false
+        }
+    }
+}
+
+/// The metadata of an entry from its `stat`.
+// The fields are narrower than u64 on some targets
+#[allow(clippy::unnecessary_cast, clippy::useless_conversion)]
+fn metadata(stat: &libc::stat) -> EntryMetadata {
+    EntryMetadata {
+        file_type: file_type(u32::from(stat.st_mode)),
+        // st_blocks counts 512-byte units whatever the block size
+        bytes: stat.st_blocks as u64 * 512,
+        links: stat.st_nlink as u64,
+        len: stat.st_size as u64,
+        modified: system_time(stat.st_mtime as i64, stat.st_mtime_nsec as u32),
+        dev: stat.st_dev as u64,
+        ino: stat.st_ino as u64,
+        mode: u32::from(stat.st_mode),
+    }
+}
+
+/// The identity of an entry from its `stat`.
+// The fields are narrower than u64 on some targets
+#[allow(clippy::unnecessary_cast)]
+fn unique_identifier(stat: &libc::stat) -> (u64, u64) {
+    (stat.st_dev as u64, stat.st_ino as u64)
+}
+
+/// The time `secs` and `nanos` from the epoch.
+pub(crate) fn system_time(secs: i64, nanos: u32) -> Option<SystemTime> {
+    let whole = match u64::try_from(secs) {
+        Ok(secs) => UNIX_EPOCH.checked_add(Duration::from_secs(secs))?,
+        Err(_) => UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))?,
+    };
+    whole.checked_add(Duration::from_nanos(nanos.into()))
+}
+
+/// The type of an entry from its mode.
+// The constants are narrower than u32 on some targets
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn file_type(mode: u32) -> EntryType {
+    let format = mode & libc::S_IFMT as u32;
+    if format == libc::S_IFREG as u32 {
+        EntryType::File
+    } else if format == libc::S_IFDIR as u32 {
+        EntryType::Dir
+    } else if format == libc::S_IFLNK as u32 {
+        EntryType::Symlink
+    } else {
+        EntryType::Special
+    }
+}
+
+/// Read up to `limit` entries from the directory `d`, after skipping `skip`.
+fn read_entries(d: &fs::File, skip: usize, limit: usize) -> io::Result<Vec<DirEntry>> {
+    // fs_at only reports names, so read the directory here to get at
+    // d_type. The duplicate shares its offset with `d`, which previous
+    // scans may have left anywhere.
+    let fd = UnixIo::duplicate_fd(d)?;
+    cvt(unsafe { libc::lseek(fd.as_raw_fd(), 0, libc::SEEK_SET) })?;
+    let mut dir = Dir::from(fd)?;
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for entry in dir.iter() {
+        let entry = entry?;
+        let name = OsStr::from_bytes(entry.file_name().to_bytes());
+        if name == OsStr::new(".") || name == OsStr::new("..") {
+            continue;
+        }
+        if skipped < skip {
+            skipped += 1;
+            continue;
+        }
+        let kind = match entry.file_type() {
+            Some(Type::Directory) => EntryKind::Dir,
+            Some(_) => EntryKind::NotDir,
+            // DT_UNKNOWN: the filesystem doesn't fill in d_type
+            None => EntryKind::Unknown,
+        };
+        entries.push(DirEntry {
+            name: name.to_os_string(),
+            kind,
+            ino: entry.ino(),
+        });
+        if entries.len() == limit {
+            break;
+        }
+    }
+    Ok(entries)
+}
+
+/// Read up to `limit` entries from the directory `d` from `position`, seeking
+/// to the offset readdir reported for the last entry read before rather than
+/// reading past the entries before it again.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn read_entries_from(
+    d: &fs::File,
+    position: &mut DirPosition,
+    limit: usize,
+) -> io::Result<Vec<DirEntry>> {
+    use nix::errno::Errno;
+    use std::ffi::CStr;
+    use std::os::unix::prelude::IntoRawFd;
+
+    /// Closes a directory stream, and the descriptor it was opened on.
+    struct Stream(*mut libc::DIR);
+
+    impl Drop for Stream {
+        fn drop(&mut self) {
+            unsafe { libc::closedir(self.0) };
+        }
+    }
+
+    let fd = UnixIo::duplicate_fd(d)?;
+    cvt(unsafe { libc::lseek(fd.as_raw_fd(), position.offset.unwrap_or(0), libc::SEEK_SET) })?;
+    let fd = fd.into_raw_fd();
+    let dir = unsafe { libc::fdopendir(fd) };
+    if dir.is_null() {
+        let e = io::Error::last_os_error();
+        drop(unsafe { File::from_raw_fd(fd) });
+        return Err(e);
+    }
+    let stream = Stream(dir);
+    let mut entries = Vec::new();
+    while entries.len() < limit {
+        Errno::clear();
+        let entry = unsafe { libc::readdir(stream.0) };
+        if entry.is_null() {
+            match Errno::last_raw() {
+                0 => break,
+                e => return Err(io::Error::from_raw_os_error(e)),
+            }
+        }
+        let entry = unsafe { &*entry };
+        position.offset = Some(entry.d_off);
+        let name = OsStr::from_bytes(unsafe { CStr::from_ptr(entry.d_name.as_ptr()) }.to_bytes());
+        if name == OsStr::new(".") || name == OsStr::new("..") {
+            continue;
+        }
+        let kind = match entry.d_type {
+            libc::DT_DIR => EntryKind::Dir,
+            // DT_UNKNOWN: the filesystem doesn't fill in d_type
+            libc::DT_UNKNOWN => EntryKind::Unknown,
+            _ => EntryKind::NotDir,
+        };
+        entries.push(DirEntry {
+            name: name.to_os_string(),
+            kind,
+            #[allow(clippy::useless_conversion)]
+            ino: entry.d_ino.into(),
+        });
+        position.entries += 1;
+    }
+    Ok(entries)
 }
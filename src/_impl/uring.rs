@@ -0,0 +1,393 @@
+//! Linux io_uring backend: the non-directories found by each directory scan
+//! are removed by submitting their syscalls to a ring in bulk, rather than
+//! making them one at a time.
+//!
+//! Entries go through the same steps as with the wrapped backend: those the
+//! scan reports as non-directories are unlinked; those of unknown type are
+//! opened as directories without following symlinks, closed, and unlinked if
+//! that failed because they are not directories. When collecting stats, the
+//! entries to unlink are first statted by name, and hard links to one file
+//! are counted as if unlinked in turn. Each step is one round of submissions
+//! for the whole batch. Directories are left to the engine.
+//!
+//! Where io_uring, or one of those operations, is unavailable the wrapped
+//! backend does all the work.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{CString, OsStr},
+    fs::File,
+    io,
+    marker::PhantomData,
+    mem::{self, ManuallyDrop},
+    os::unix::{ffi::OsStrExt, io::AsRawFd},
+    path::Path,
+};
+
+use io_uring::{opcode, register::Probe, squeue, types, IoUring};
+
+use super::{
+    io::{BatchError, DirEntry, DirPosition, EntryKind, EntryMetadata, Io},
+    unix::{file_type, system_time},
+};
+use crate::{Filesystem, Operation, RemoveErrorKind};
+
+/// Operations in flight at once.
+const RING_ENTRIES: u32 = 256;
+
+thread_local! {
+    /// `None` if io_uring can't be used on this system, or has failed.
+    static RING: RefCell<Option<IoUring>> = RefCell::new(new_ring());
+}
+
+fn new_ring() -> Option<IoUring> {
+    let ring = IoUring::new(RING_ENTRIES).ok()?;
+    let mut probe = Probe::new();
+    ring.submitter().register_probe(&mut probe).ok()?;
+    [
+        opcode::OpenAt::CODE,
+        opcode::Statx::CODE,
+        opcode::Close::CODE,
+        opcode::UnlinkAt::CODE,
+    ]
+    .into_iter()
+    .all(|op| probe.is_supported(op))
+    .then_some(ring)
+}
+
+/// Whether batches are submitted to io_uring on this thread.
+#[cfg(test)]
+pub(super) fn is_available() -> bool {
+    RING.with(|ring| ring.borrow().is_some())
+}
+
+/// The ring itself failed during a round of `Operation`s, so operations may
+/// still be in flight: the buffers they use must be leaked and the ring never
+/// used again.
+struct RingFailed(Operation, io::Error);
+
+/// Submit `ops`, a round of `operation`s, and wait for all of them, returning
+/// each one's result. The buffers the operations point into must outlive the
+/// call, and must be leaked if it fails.
+fn run(
+    ring: &mut IoUring,
+    operation: Operation,
+    ops: Vec<squeue::Entry>,
+) -> Result<Vec<io::Result<u32>>, RingFailed> {
+    let mut results = Vec::with_capacity(ops.len());
+    for chunk in ops.chunks(RING_ENTRIES as usize) {
+        let base = results.len();
+        results.extend(chunk.iter().map(|_| Ok(0)));
+        for (i, op) in chunk.iter().enumerate() {
+            let op = op.clone().user_data((base + i) as u64);
+            // Safety: the caller keeps the buffers alive, as documented.
+            unsafe { ring.submission().push(&op) }
+                .expect("the submission queue holds a whole chunk");
+        }
+        let mut reaped = 0;
+        while reaped < chunk.len() {
+            match ring.submit_and_wait(chunk.len() - reaped) {
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(RingFailed(operation, e)),
+            }
+            for cqe in ring.completion() {
+                results[cqe.user_data() as usize] = match cqe.result() {
+                    r if r < 0 => Err(io::Error::from_raw_os_error(-r)),
+                    r => Ok(r as u32),
+                };
+                reaped += 1;
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// The metadata of an entry from its `statx`.
+fn metadata(stat: &libc::statx) -> EntryMetadata {
+    EntryMetadata {
+        file_type: file_type(u32::from(stat.stx_mode)),
+        bytes: stat.stx_blocks * 512,
+        links: u64::from(stat.stx_nlink),
+        len: stat.stx_size,
+        modified: system_time(stat.stx_mtime.tv_sec, stat.stx_mtime.tv_nsec),
+        dev: libc::makedev(stat.stx_dev_major, stat.stx_dev_minor),
+        ino: stat.stx_ino,
+        mode: u32::from(stat.stx_mode),
+    }
+}
+
+/// Remove the non-directories among `entries` of `parent` through `ring`,
+/// adding their usage to `usage` if given.
+fn remove_batch<I: Io>(
+    ring: &mut IoUring,
+    parent: &File,
+    entries: Vec<DirEntry>,
+    usage: Option<&mut Vec<EntryMetadata>>,
+) -> Result<Result<Vec<DirEntry>, BatchError>, RingFailed> {
+    let dirfd = types::Fd(parent.as_raw_fd());
+    let names = match entries
+        .iter()
+        .map(|entry| CString::new(entry.name.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        // Leaked by `?` below if the ring fails
+        Ok(names) => ManuallyDrop::new(names),
+        Err(e) => {
+            return Ok(Err(BatchError {
+                name: None,
+                operation: Operation::Open,
+                error: e.into(),
+            }))
+        }
+    };
+    let mut kinds: Vec<EntryKind> = entries.iter().map(|entry| entry.kind).collect();
+    // Entries known not to be directories through a handle, rather than
+    // only by the scan.
+    let mut checked = vec![false; entries.len()];
+    let mut removed = vec![false; entries.len()];
+    let mut errors: Vec<(usize, Operation, io::Error)> = Vec::new();
+
+    // Open the entries of unknown type as directories: anything else fails
+    // without being opened, so FIFOs don't block and devices aren't touched.
+    // Each chunk is closed again before the next is opened, bounding the
+    // descriptors held.
+    let unknown: Vec<usize> = (0..entries.len())
+        .filter(|&i| kinds[i] == EntryKind::Unknown)
+        .collect();
+    for chunk in unknown.chunks(RING_ENTRIES as usize) {
+        let ops = chunk
+            .iter()
+            .map(|&i| {
+                opcode::OpenAt::new(dirfd, names[i].as_ptr())
+                    .flags(
+                        libc::O_RDONLY
+                            | libc::O_DIRECTORY
+                            | libc::O_NOFOLLOW
+                            | libc::O_NONBLOCK
+                            | libc::O_CLOEXEC
+                            | libc::O_NOCTTY,
+                    )
+                    .build()
+            })
+            .collect();
+        let mut opened = Vec::new();
+        for (&i, result) in chunk.iter().zip(run(ring, Operation::Open, ops)?) {
+            match result {
+                Ok(fd) => {
+                    kinds[i] = EntryKind::Dir;
+                    opened.push(fd as i32);
+                }
+                Err(e) if I::is_not_dir_open_error(&e) => {
+                    kinds[i] = EntryKind::NotDir;
+                    checked[i] = true;
+                }
+                // Left for the engine, which can shed cached handles and retry
+                Err(e) if I::is_handle_exhaustion_error(&e) => (),
+                Err(e) => errors.push((i, Operation::Open, e)),
+            }
+        }
+        // Close them again; like dropping a File, this ignores errors. Should
+        // the ring fail, it is reported against opening the batch.
+        let ops = opened
+            .into_iter()
+            .map(|fd| opcode::Close::new(types::Fd(fd)).build())
+            .collect();
+        run(ring, Operation::Open, ops)?;
+    }
+
+    let mut unlink: Vec<usize> = (0..entries.len())
+        .filter(|&i| kinds[i] == EntryKind::NotDir)
+        .collect();
+
+    // Read their usage, for the report
+    let mut usages = Vec::new();
+    if usage.is_some() {
+        let mut stats = ManuallyDrop::new(vec![
+            // Safety: statx is plain old data
+            unsafe { mem::zeroed::<libc::statx>() };
+            unlink.len()
+        ]);
+        let ops = unlink
+            .iter()
+            .zip(stats.iter_mut())
+            .map(|(&i, stat)| {
+                opcode::Statx::new(dirfd, names[i].as_ptr(), (stat as *mut libc::statx).cast())
+                    .flags(libc::AT_SYMLINK_NOFOLLOW)
+                    .mask(libc::STATX_BASIC_STATS)
+                    .build()
+            })
+            .collect();
+        let results = run(ring, Operation::Stat, ops)?;
+        let mut statted = Vec::with_capacity(unlink.len());
+        for ((&i, result), stat) in unlink.iter().zip(results).zip(&*stats) {
+            match result {
+                Ok(_) => {
+                    statted.push(i);
+                    usages.push(metadata(stat));
+                }
+                Err(e) => errors.push((i, Operation::Stat, e)),
+            }
+        }
+        drop(ManuallyDrop::into_inner(stats));
+        unlink = statted;
+    }
+
+    // Unlink the non-directories
+    let ops = unlink
+        .iter()
+        .map(|&i| opcode::UnlinkAt::new(dirfd, names[i].as_ptr()).build())
+        .collect();
+    let results = run(ring, Operation::Unlink, ops)?;
+    let mut freed = Vec::new();
+    for (k, (&i, result)) in unlink.iter().zip(results).enumerate() {
+        match result {
+            Ok(_) => {
+                removed[i] = true;
+                if usage.is_some() {
+                    freed.push(usages[k]);
+                }
+            }
+            // Replaced by a directory since the scan
+            Err(e) if !checked[i] && I::is_dir_unlink_error(&e) => kinds[i] = EntryKind::Unknown,
+            Err(e) => errors.push((i, Operation::Unlink, e)),
+        }
+    }
+    drop(ManuallyDrop::into_inner(names));
+    if let Some(usage) = usage {
+        // Links to one file were all statted before any was unlinked; count
+        // each with the links it would have had when unlinked one at a time,
+        // so that the last one frees the file, as without a ring
+        let mut unlinked: HashMap<(u64, u64), u64> = HashMap::new();
+        for metadata in &mut freed {
+            if metadata.links > 1 {
+                let earlier = unlinked.entry((metadata.dev, metadata.ino)).or_default();
+                metadata.links = metadata.links.saturating_sub(*earlier);
+                *earlier += 1;
+            }
+        }
+        usage.extend(freed);
+    }
+
+    // Report the error that removing entries one at a time would hit first
+    if let Some((i, operation, error)) = errors.into_iter().min_by_key(|(i, ..)| *i) {
+        return Ok(Err(BatchError {
+            name: Some(entries.into_iter().nth(i).expect("index of an entry").name),
+            operation,
+            error,
+        }));
+    }
+    Ok(Ok(entries
+        .into_iter()
+        .zip(kinds)
+        .zip(removed)
+        .filter(|(_, removed)| !removed)
+        .map(|((mut entry, kind), _)| {
+            entry.kind = kind;
+            entry
+        })
+        .collect()))
+}
+
+/// Wraps the backend `I`, doing its bulk removals through io_uring.
+pub(crate) struct UringIo<I>(PhantomData<I>);
+
+impl<I: Io> Io for UringIo<I> {
+    type UniqueIdentifier = I::UniqueIdentifier;
+
+    fn duplicate_fd(f: &File) -> io::Result<File> {
+        I::duplicate_fd(f)
+    }
+
+    fn open_dir(p: &Path) -> io::Result<File> {
+        I::open_dir(p)
+    }
+
+    fn open_dir_at(parent: &File, name: &OsStr) -> io::Result<File> {
+        I::open_dir_at(parent, name)
+    }
+
+    fn read_dir(d: &File, limit: usize) -> io::Result<Vec<DirEntry>> {
+        I::read_dir(d, limit)
+    }
+
+    fn read_dir_from(
+        d: &File,
+        position: &mut DirPosition,
+        limit: usize,
+    ) -> io::Result<Vec<DirEntry>> {
+        I::read_dir_from(d, position, limit)
+    }
+
+    fn remove_batch(
+        parent: &File,
+        entries: Vec<DirEntry>,
+        usage: Option<&mut Vec<EntryMetadata>>,
+    ) -> Result<Vec<DirEntry>, BatchError> {
+        RING.with(|ring| {
+            let mut ring = ring.borrow_mut();
+            let Some(uring) = ring.as_mut() else {
+                return I::remove_batch(parent, entries, usage);
+            };
+            remove_batch::<I>(uring, parent, entries, usage).unwrap_or_else(
+                |RingFailed(operation, e)| {
+                    #[cfg(feature = "log")]
+                    log::debug!("io_uring failed, no longer using it: {}", e);
+                    // Operations may still be in flight: leak the ring rather
+                    // than tear it down, and don't use it again.
+                    mem::forget(ring.take());
+                    Err(BatchError {
+                        name: None,
+                        operation,
+                        error: e,
+                    })
+                },
+            )
+        })
+    }
+
+    fn metadata(f: &File) -> io::Result<EntryMetadata> {
+        I::metadata(f)
+    }
+
+    fn metadata_at(parent: &File, name: &OsStr) -> io::Result<EntryMetadata> {
+        I::metadata_at(parent, name)
+    }
+
+    fn filesystem(d: &File) -> io::Result<Filesystem> {
+        I::filesystem(d)
+    }
+
+    fn open_dir_beneath(base: &File, path: &Path) -> Option<io::Result<File>> {
+        I::open_dir_beneath(base, path)
+    }
+
+    fn unique_identifier(d: &File) -> io::Result<Self::UniqueIdentifier> {
+        I::unique_identifier(d)
+    }
+
+    fn unique_identifier_at(parent: &File, name: &OsStr) -> io::Result<Self::UniqueIdentifier> {
+        I::unique_identifier_at(parent, name)
+    }
+
+    fn is_not_dir_open_error(e: &io::Error) -> bool {
+        I::is_not_dir_open_error(e)
+    }
+
+    fn is_dir_unlink_error(e: &io::Error) -> bool {
+        I::is_dir_unlink_error(e)
+    }
+
+    fn error_kind(e: &io::Error) -> RemoveErrorKind {
+        I::error_kind(e)
+    }
+
+    fn is_immutable(parent: &File, name: &OsStr) -> bool {
+        I::is_immutable(parent, name)
+    }
+
+    fn is_handle_exhaustion_error(e: &io::Error) -> bool {
+        I::is_handle_exhaustion_error(e)
+    }
+}
@@ -1,26 +1,36 @@
 use std::{
-    ffi::c_void,
+    ffi::{c_void, OsStr},
     fs::{File, OpenOptions},
     io::{self, Result},
-    mem::MaybeUninit,
+    mem::{self, MaybeUninit},
     os::windows::fs::OpenOptionsExt,
     os::windows::prelude::AsRawHandle,
     os::windows::prelude::*,
     path::Path,
 };
 
+use fs_at::os::windows::OpenOptionsExt as _;
 use windows_sys::Win32::{
-    Foundation::{DuplicateHandle, DUPLICATE_SAME_ACCESS, HANDLE},
-    Storage::FileSystem::{FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT},
+    Foundation::{
+        DuplicateHandle, DUPLICATE_SAME_ACCESS, ERROR_ACCESS_DENIED, ERROR_DIRECTORY,
+        ERROR_DIR_NOT_EMPTY, ERROR_FILE_NOT_FOUND, ERROR_LOCK_VIOLATION, ERROR_PATH_NOT_FOUND,
+        ERROR_SHARING_VIOLATION, ERROR_TOO_MANY_OPEN_FILES, ERROR_WRITE_PROTECT, HANDLE,
+    },
+    Storage::FileSystem::{
+        FileStandardInfo, GetFileInformationByHandleEx, DELETE, FILE_FLAG_BACKUP_SEMANTICS,
+        FILE_FLAG_OPEN_REPARSE_POINT, FILE_LIST_DIRECTORY, FILE_READ_ATTRIBUTES,
+        FILE_STANDARD_INFO,
+    },
     System::Threading::GetCurrentProcess,
 };
 
-use super::io::Io;
+use super::io::{DirEntry, EntryKind, EntryMetadata, EntryType, Io};
+use crate::{Filesystem, RemoveErrorKind};
 
 pub(crate) struct WindowsIo;
 
 impl Io for WindowsIo {
-    fn duplicate_fd(f: &mut File) -> io::Result<File> {
+    fn duplicate_fd(f: &File) -> io::Result<File> {
         let mut new_handle: MaybeUninit<*mut c_void> = MaybeUninit::uninit();
 
         let result = unsafe {
@@ -55,4 +65,92 @@ impl Io for WindowsIo {
         }
         Ok(maybe_dir)
     }
+
+    fn open_dir_at(parent: &File, name: &OsStr) -> Result<File> {
+        // Could possibly drop a syscall by dropping FILE_READ_ATTRIBUTES
+        // and trusting read_dir metadata more. OTOH that would introduce a
+        // race :/.
+        let mut opts = fs_at::OpenOptions::default();
+        opts.desired_access(DELETE | FILE_LIST_DIRECTORY | FILE_READ_ATTRIBUTES);
+        opts.open_path_at(parent, name)
+    }
+
+    fn read_dir(d: &File, limit: usize) -> Result<Vec<DirEntry>> {
+        // fs_at reads through a handle of its own
+        let mut d = Self::duplicate_fd(d)?;
+        let mut entries = Vec::new();
+        for entry in fs_at::read_dir(&mut d)? {
+            let entry = entry?;
+            let name = entry.name();
+            if name == OsStr::new(".") || name == OsStr::new("..") {
+                continue;
+            }
+            // Every entry is opened to be deleted by handle anyway, so its
+            // type is taken from the handle rather than the scan.
+            entries.push(DirEntry {
+                name: name.to_os_string(),
+                kind: EntryKind::Unknown,
+            });
+            if entries.len() == limit {
+                break;
+            }
+        }
+        Ok(entries)
+    }
+
+    fn metadata(f: &File) -> Result<EntryMetadata> {
+        let mut info = MaybeUninit::<FILE_STANDARD_INFO>::uninit();
+        let result = unsafe {
+            GetFileInformationByHandleEx(
+                f.as_raw_handle() as HANDLE,
+                FileStandardInfo,
+                info.as_mut_ptr().cast(),
+                mem::size_of::<FILE_STANDARD_INFO>() as u32,
+            )
+        };
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let info = unsafe { info.assume_init() };
+        // Handles are opened without following reparse points
+        let metadata = f.metadata()?;
+        let file_type = if metadata.file_type().is_symlink() {
+            EntryType::Symlink
+        } else if info.Directory {
+            EntryType::Dir
+        } else {
+            EntryType::File
+        };
+        Ok(EntryMetadata {
+            file_type,
+            bytes: info.AllocationSize as u64,
+            links: u64::from(info.NumberOfLinks),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    fn filesystem(_d: &File) -> Result<Filesystem> {
+        // None of the policies are specific to Windows filesystems
+        Ok(Filesystem::Other)
+    }
+
+    fn error_kind(e: &io::Error) -> RemoveErrorKind {
+        match e.raw_os_error().and_then(|code| u32::try_from(code).ok()) {
+            // Gone since the scan, or new entries since the directory was
+            // emptied
+            Some(ERROR_FILE_NOT_FOUND | ERROR_PATH_NOT_FOUND | ERROR_DIR_NOT_EMPTY) => {
+                RemoveErrorKind::Raced
+            }
+            Some(ERROR_ACCESS_DENIED) => RemoveErrorKind::PermissionDenied,
+            Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION) => RemoveErrorKind::Busy,
+            Some(ERROR_DIRECTORY) => RemoveErrorKind::NotADirectory,
+            Some(ERROR_WRITE_PROTECT) => RemoveErrorKind::ReadOnlyFilesystem,
+            _ => RemoveErrorKind::Other,
+        }
+    }
+
+    fn is_handle_exhaustion_error(e: &io::Error) -> bool {
+        e.raw_os_error() == Some(ERROR_TOO_MANY_OPEN_FILES as i32)
+    }
 }
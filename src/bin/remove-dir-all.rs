@@ -1,6 +1,8 @@
 use std::{io::Result, path::PathBuf};
 
 use clap::{Parser, ValueEnum};
+#[cfg(not(windows))]
+use remove_dir_all::OnMountPoint;
 
 /// What kind of parallelism to use
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -9,6 +11,17 @@ enum Parallelism {
     Serial,
     /// Parallelise readdir and unlink operations
     Parallel,
+    /// Parallelise readdir and unlink operations on std threads
+    Threads,
+}
+
+/// What to do at a mount point with --one-file-system
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum AtMountPoint {
+    /// Leave it in place, with the directories holding it, and say so
+    Skip,
+    /// Stop with an error
+    Fail,
 }
 
 /// Simple CLI to use remove-dir-alls recursive deletion logic from the command
@@ -22,20 +35,104 @@ struct Cli {
     /// Choose the parallelism strategy
     #[arg(short = 'p', long = "parallelism")]
     parallelism: Option<Parallelism>,
+    /// Number of threads for parallel deletion [default: one per CPU]
+    #[arg(short = 'j', long = "jobs", value_name = "N")]
+    jobs: Option<usize>,
+    /// Tune the number of operations in flight to the filesystem as it goes
+    #[arg(long = "adaptive")]
+    adaptive: bool,
+    /// Print what was removed from each path, and how long it took
+    #[arg(long = "stats")]
+    stats: bool,
+    /// Print what would be removed from each path, without removing anything
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+    /// Don't remove anything on a filesystem mounted under a path
+    #[arg(
+        long = "one-file-system",
+        value_name = "AT_MOUNT_POINT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "skip"
+    )]
+    one_file_system: Option<AtMountPoint>,
 }
 
 fn main() -> Result<()> {
     env_logger::init();
     let cli = Cli::parse();
 
-    let remover = match cli.parallelism {
-        None => remove_dir_all::RemoverBuilder::new().build(),
-        Some(Parallelism::Serial) => remove_dir_all::RemoverBuilder::new().serial().build(),
-        Some(Parallelism::Parallel) => remove_dir_all::RemoverBuilder::new().parallel().build(),
+    let builder = remove_dir_all::RemoverBuilder::new();
+    let builder = match (cli.parallelism, cli.jobs) {
+        (None, None) => builder,
+        (Some(Parallelism::Serial), _) => builder.serial(),
+        (Some(Parallelism::Parallel), None) => builder.parallel(),
+        (None | Some(Parallelism::Parallel), Some(jobs)) => builder.threads(jobs),
+        (Some(Parallelism::Threads), jobs) => builder.std_threads(jobs.unwrap_or(0)),
     };
+    let builder = if cli.adaptive {
+        builder.adaptive()
+    } else {
+        builder
+    };
+    let builder = if cli.stats { builder.stats() } else { builder };
+    #[cfg(not(windows))]
+    let builder = match cli.one_file_system {
+        None => builder,
+        Some(AtMountPoint::Skip) => builder.one_file_system(OnMountPoint::Skip),
+        Some(AtMountPoint::Fail) => builder.one_file_system(OnMountPoint::Fail),
+    };
+    #[cfg(windows)]
+    if cli.one_file_system.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "--one-file-system is not supported on Windows",
+        ));
+    }
+    let remover = builder.build();
 
     for p in cli.names {
-        remover.remove_dir_all(&p)?;
+        if cli.dry_run {
+            let plan = remover.plan(&p)?;
+            for entry in plan.entries() {
+                println!(
+                    "{} {:?} {} bytes",
+                    plan.root().join(entry.relative_path()).display(),
+                    entry.file_type(),
+                    entry.metadata().len(),
+                );
+            }
+            println!(
+                "{}: would remove {} entries, freeing {} bytes",
+                p.display(),
+                plan.entries().len() + 1,
+                plan.bytes(),
+            );
+            continue;
+        }
+        let report = remover.remove_dir_all_with_report(&p)?;
+        for mount_point in report.mount_points() {
+            eprintln!(
+                "skipped {}: on a different filesystem",
+                mount_point.display()
+            );
+        }
+        if cli.stats {
+            println!(
+                "{}: removed files={} dirs={} symlinks={} special={}, freed {} bytes, \
+                 in {:?} (scan {:?}, unlink {:?}, rmdir {:?})",
+                p.display(),
+                report.files(),
+                report.dirs(),
+                report.symlinks(),
+                report.special_files(),
+                report.bytes_freed(),
+                report.elapsed(),
+                report.scan_time(),
+                report.unlink_time(),
+                report.rmdir_time(),
+            );
+        }
     }
     Ok(())
 }
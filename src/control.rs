@@ -0,0 +1,128 @@
+//! Stopping and pausing a removal from another thread.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// A handle to cancel, pause and resume the removals of a
+/// [`Remover`](crate::Remover), registered with
+/// [`RemoverBuilder::control`](crate::RemoverBuilder::control).
+///
+/// Clones share their state, so one can be kept to control removals running
+/// on other threads. Removal checks the handle before each syscall that reads
+/// or removes, and stops or waits there: a cancelled removal fails with
+/// [`RemoveErrorKind::Cancelled`](crate::RemoveErrorKind::Cancelled), leaving
+/// whatever it had not yet removed in place, and
+/// [`RemoveError::report`](crate::RemoveError::report) tells how far it got.
+///
+/// Cancelling is final: removals started with a cancelled handle fail
+/// straight away.
+#[derive(Debug, Clone, Default)]
+pub struct RemovalControl {
+    state: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    cancelled: AtomicBool,
+    paused: AtomicBool,
+    /// Held to change either flag, so that waiters can't miss a change.
+    lock: Mutex<()>,
+    changed: Condvar,
+}
+
+/// Why removal stopped early.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Stop {
+    Cancelled,
+    TimedOut,
+}
+
+impl RemovalControl {
+    /// Create a handle, neither cancelled nor paused.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop removal at the next check, waking it if paused.
+    pub fn cancel(&self) {
+        self.set(&self.state.cancelled, true);
+    }
+
+    /// Whether [`RemovalControl::cancel`] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Have removal wait at the next check until resumed, cancelled, or past
+    /// its [deadline](crate::RemoverBuilder::deadline). Descriptors held so
+    /// far stay open while it waits.
+    pub fn pause(&self) {
+        self.set(&self.state.paused, true);
+    }
+
+    /// Let a paused removal carry on.
+    pub fn resume(&self) {
+        self.set(&self.state.paused, false);
+    }
+
+    /// Whether removal is paused.
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::Acquire)
+    }
+
+    fn set(&self, flag: &AtomicBool, value: bool) {
+        let _lock = self.state.lock.lock().unwrap();
+        flag.store(value, Ordering::Release);
+        self.state.changed.notify_all();
+    }
+
+    /// Wait while paused, and say whether to stop: if cancelled, if
+    /// `abandoned` is set, or once `deadline` has passed.
+    pub(crate) fn check(
+        &self,
+        deadline: Option<Instant>,
+        abandoned: Option<&AtomicBool>,
+    ) -> Option<Stop> {
+        let state = &*self.state;
+        if self.is_paused() {
+            let mut lock = state.lock.lock().unwrap();
+            while self.is_paused() && stop(deadline, abandoned).is_none() && !self.is_cancelled() {
+                // Nothing signals `abandoned` being set, so it is polled
+                let timeout = [
+                    deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())),
+                    abandoned.map(|_| ABANDONED_POLL),
+                ];
+                lock = match timeout.into_iter().flatten().min() {
+                    Some(timeout) => state.changed.wait_timeout(lock, timeout).unwrap().0,
+                    None => state.changed.wait(lock).unwrap(),
+                };
+            }
+        }
+        if self.is_cancelled() {
+            Some(Stop::Cancelled)
+        } else {
+            stop(deadline, abandoned)
+        }
+    }
+}
+
+/// How often a paused removal checks whether it has been abandoned.
+const ABANDONED_POLL: Duration = Duration::from_millis(50);
+
+/// Whether to stop without a [`RemovalControl`]: once `abandoned` is set,
+/// by the future of an async removal being dropped, or `deadline` has
+/// passed.
+pub(crate) fn stop(deadline: Option<Instant>, abandoned: Option<&AtomicBool>) -> Option<Stop> {
+    if abandoned.is_some_and(|abandoned| abandoned.load(Ordering::Acquire)) {
+        Some(Stop::Cancelled)
+    } else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        Some(Stop::TimedOut)
+    } else {
+        None
+    }
+}
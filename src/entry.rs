@@ -0,0 +1,210 @@
+//! The entries of a tree, as hooks see them.
+
+use std::{fmt, path::Path, sync::Arc, time::SystemTime};
+
+/// What to do with an entry, as decided by a
+/// [`RemoverBuilder::before_remove`](crate::RemoverBuilder::before_remove)
+/// hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookAction {
+    /// Remove the entry. A directory is emptied first, with the hook called on
+    /// each of its entries.
+    Remove,
+    /// Leave the entry in place, and with it the directories holding it. This
+    /// is not a failure.
+    Skip,
+    /// Stop removing, failing with
+    /// [`RemoveErrorKind::Aborted`](crate::RemoveErrorKind::Aborted).
+    Abort,
+}
+
+/// An entry of the tree being removed, as given to hooks.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    pub(crate) relative_path: &'a Path,
+    pub(crate) metadata: &'a EntryMetadata,
+}
+
+impl<'a> Entry<'a> {
+    /// The path of the entry, relative to the directory being removed or
+    /// emptied.
+    pub fn relative_path(&self) -> &'a Path {
+        self.relative_path
+    }
+
+    /// The type of the entry.
+    pub fn file_type(&self) -> EntryType {
+        self.metadata.file_type
+    }
+
+    /// The metadata of the entry, read just before it was removed.
+    pub fn metadata(&self) -> &'a EntryMetadata {
+        self.metadata
+    }
+}
+
+/// The type of an entry, read without following symlinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EntryType {
+    /// A regular file.
+    File,
+    /// A directory.
+    Dir,
+    /// A symlink; on Windows, a junction or other reparse point too.
+    Symlink,
+    /// A FIFO, socket or device.
+    Special,
+}
+
+/// What an entry was just before it was removed, read without following
+/// symlinks.
+#[derive(Debug, Clone, Copy)]
+pub struct EntryMetadata {
+    pub(crate) file_type: EntryType,
+    /// Bytes allocated to the entry, which may differ from its length.
+    pub(crate) bytes: u64,
+    /// Hard links to the entry, including the one about to be removed.
+    pub(crate) links: u64,
+    pub(crate) len: u64,
+    pub(crate) modified: Option<SystemTime>,
+    #[cfg(not(windows))]
+    pub(crate) dev: u64,
+    #[cfg(not(windows))]
+    pub(crate) ino: u64,
+    #[cfg(not(windows))]
+    pub(crate) mode: u32,
+}
+
+impl EntryMetadata {
+    /// The type of the entry.
+    pub fn file_type(&self) -> EntryType {
+        self.file_type
+    }
+
+    /// The length of the entry, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the entry is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The bytes allocated to the entry, which for sparse or compressed
+    /// files is less than their length.
+    pub fn allocated(&self) -> u64 {
+        self.bytes
+    }
+
+    /// The number of hard links to the entry, counting the one in the tree.
+    pub fn links(&self) -> u64 {
+        self.links
+    }
+
+    /// When the entry was last modified, where the platform records it.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// The device holding the entry (`st_dev`).
+    #[cfg(not(windows))]
+    pub fn dev(&self) -> u64 {
+        self.dev
+    }
+
+    /// The inode number of the entry (`st_ino`).
+    #[cfg(not(windows))]
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// The identity of the entry, its device and inode number.
+    #[cfg(not(windows))]
+    pub fn id(&self) -> EntryId {
+        EntryId::new(self.dev, self.ino)
+    }
+
+    /// The type and permission bits of the entry (`st_mode`).
+    #[cfg(not(windows))]
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// Whether this and `other` are the metadata of the same entry: of the
+    /// same type, and on Unix the same device and inode. Windows has no
+    /// inode numbers in this metadata, so there only the type is compared.
+    pub(crate) fn is_same_entry(&self, other: &EntryMetadata) -> bool {
+        #[cfg(not(windows))]
+        if (self.dev, self.ino) != (other.dev, other.ino) {
+            return false;
+        }
+        self.file_type == other.file_type
+    }
+
+    /// The bytes removing the entry frees. The blocks of a file are only
+    /// freed with its last link, though the count of links includes
+    /// directories' own entries and subdirectories.
+    pub(crate) fn bytes_freed(&self) -> u64 {
+        if self.links <= 1 || self.file_type == EntryType::Dir {
+            self.bytes
+        } else {
+            0
+        }
+    }
+}
+
+/// The identity of an entry on Unix: its device and inode number, which no
+/// other entry shares while it exists.
+#[cfg(not(windows))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EntryId {
+    dev: u64,
+    ino: u64,
+}
+
+#[cfg(not(windows))]
+impl EntryId {
+    /// The identity of the entry with inode number `ino` on device `dev`,
+    /// such as [`MetadataExt::dev`](std::os::unix::fs::MetadataExt::dev) and
+    /// [`MetadataExt::ino`](std::os::unix::fs::MetadataExt::ino) give.
+    pub fn new(dev: u64, ino: u64) -> Self {
+        Self { dev, ino }
+    }
+
+    /// The device holding the entry (`st_dev`).
+    pub fn dev(&self) -> u64 {
+        self.dev
+    }
+
+    /// The inode number of the entry (`st_ino`).
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+}
+
+type BeforeRemove = dyn Fn(&Entry<'_>) -> HookAction + Send + Sync;
+type AfterRemove = dyn Fn(&Entry<'_>) + Send + Sync;
+
+/// The hooks registered on a remover, shared by its clones.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub(crate) before: Option<Arc<BeforeRemove>>,
+    pub(crate) after: Option<Arc<AfterRemove>>,
+}
+
+impl Hooks {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.before.is_none() && self.after.is_none()
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("before", &self.before.is_some())
+            .field("after", &self.after.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,238 @@
+//! The error returned by [`Remover`](crate::Remover): which operation failed
+//! on which path, and why.
+
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt::{self, Display},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::RemovalReport;
+
+/// An operation made while removing a tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operation {
+    /// Creating the directory, for [`Remover::ensure_empty_dir`](crate::Remover::ensure_empty_dir).
+    CreateDir,
+    /// Opening a directory, or an entry to find out whether it is one.
+    Open,
+    /// Reading the names in a directory.
+    ReadDir,
+    /// Reading the type of an entry through its handle.
+    Stat,
+    /// Unlinking a non-directory.
+    Unlink,
+    /// Removing an emptied directory.
+    Rmdir,
+    /// Deleting an entry through its handle, on Windows.
+    DeleteByHandle,
+    /// Starting threads for parallel removal.
+    Spawn,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::CreateDir => "create directory",
+            Operation::Open => "open",
+            Operation::ReadDir => "read directory",
+            Operation::Stat => "stat",
+            Operation::Unlink => "unlink",
+            Operation::Rmdir => "remove directory",
+            Operation::DeleteByHandle => "delete",
+            Operation::Spawn => "start threads to remove",
+        })
+    }
+}
+
+/// Why an operation failed, classified from the OS error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RemoveErrorKind {
+    /// The path to remove doesn't exist.
+    NotFound,
+    /// Permission was denied.
+    PermissionDenied,
+    /// The entry is in use: a mount point, or on Windows open without
+    /// sharing delete access.
+    Busy,
+    /// The tree changed while it was being removed: an entry vanished, a
+    /// directory gained entries, or an entry was replaced.
+    Raced,
+    /// The directory at the path to remove is no longer the one removal
+    /// started on, so it was left in place: it was replaced once its contents
+    /// were removed, or since a [`DeletionPlan`](crate::DeletionPlan) was
+    /// made.
+    Replaced,
+    /// The path to remove isn't beneath the base directory given to
+    /// `RemoverBuilder::beneath`: it is absolute, goes up with `..`, passes
+    /// through a symlink, or names the base itself.
+    NotBeneath,
+    /// A directory along the path to remove is a symlink, which
+    /// `RemoverBuilder::strict_paths` refuses to follow.
+    SymlinkInPath,
+    /// A directory in the tree is a mount point, which
+    /// `RemoverBuilder::one_file_system` was asked not to cross.
+    MountPoint,
+    /// A path that should be a directory isn't one.
+    NotADirectory,
+    /// The filesystem is mounted read-only.
+    ReadOnlyFilesystem,
+    /// The entry, or the directory holding it, is marked immutable or
+    /// append-only (`chattr +i`/`+a` on Linux, `chflags` on BSD and macOS).
+    Immutable,
+    /// A hook aborted removal; see
+    /// [`HookAction::Abort`](crate::HookAction::Abort). Removal ends even in
+    /// keep-going mode.
+    Aborted,
+    /// Removal was stopped with
+    /// [`RemovalControl::cancel`](crate::RemovalControl::cancel). Removal ends
+    /// even in keep-going mode.
+    Cancelled,
+    /// Removal ran past its
+    /// [deadline](crate::RemoverBuilder::deadline). Removal ends even in
+    /// keep-going mode.
+    TimedOut,
+    /// Anything else.
+    Other,
+}
+
+/// The failure of one operation while removing a tree.
+///
+/// In [keep-going](crate::RemoverBuilder::keep_going) mode this is the first
+/// failure, and also carries every later one and the directories they left
+/// behind.
+///
+/// This converts into an [`io::Error`] of the same [`io::ErrorKind`] as the
+/// OS error, which wraps the `RemoveError` for `?` in functions returning
+/// [`io::Result`]; [`RemoveError::into_io_error`] gives the bare OS error.
+#[derive(Debug)]
+pub struct RemoveError {
+    path: OsString,
+    operation: Operation,
+    kind: RemoveErrorKind,
+    error: io::Error,
+    rest: Option<Box<Rest>>,
+    report: Option<Box<RemovalReport>>,
+}
+
+/// What else went wrong, in keep-going mode.
+#[derive(Debug)]
+struct Rest {
+    failures: Vec<RemoveError>,
+    remaining: Vec<PathBuf>,
+}
+
+impl RemoveError {
+    pub(crate) fn new(
+        path: OsString,
+        operation: Operation,
+        kind: RemoveErrorKind,
+        error: io::Error,
+    ) -> Self {
+        Self {
+            path,
+            operation,
+            kind,
+            error,
+            rest: None,
+            report: None,
+        }
+    }
+
+    /// Attach what the removal did before it ended.
+    pub(crate) fn with_report(mut self, report: RemovalReport) -> Self {
+        self.report = Some(Box::new(report));
+        self
+    }
+
+    /// Attach the failures after this one, and the directories left in place.
+    pub(crate) fn with_rest(mut self, failures: Vec<RemoveError>, remaining: Vec<PathBuf>) -> Self {
+        self.rest = Some(Box::new(Rest {
+            failures,
+            remaining,
+        }));
+        self
+    }
+
+    /// The path the operation was made on, starting from the path given to
+    /// the [`Remover`](crate::Remover), or the `debug_root` given to
+    /// [`RemoveDir`](crate::RemoveDir).
+    pub fn path(&self) -> &Path {
+        Path::new(&self.path)
+    }
+
+    /// The operation that failed.
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    /// Why it failed.
+    pub fn kind(&self) -> RemoveErrorKind {
+        self.kind
+    }
+
+    /// The error reported by the OS.
+    pub fn io_error(&self) -> &io::Error {
+        &self.error
+    }
+
+    /// Every failure of the removal: this one, then in keep-going mode those
+    /// after it.
+    pub fn failures(&self) -> impl Iterator<Item = &RemoveError> {
+        let rest = self.rest.iter().flat_map(|rest| &rest.failures);
+        std::iter::once(self).chain(rest)
+    }
+
+    /// In keep-going mode, the directories left in place because something
+    /// in them could not be removed or read, deepest first. The failures
+    /// themselves are not included.
+    pub fn remaining(&self) -> &[PathBuf] {
+        self.rest.as_ref().map_or(&[], |rest| &rest.remaining)
+    }
+
+    /// What the removal did before it ended, such as how far a cancelled
+    /// removal got. This is attached to the errors a
+    /// [`Remover`](crate::Remover) returns once removal has begun; as ever,
+    /// the counts need [`RemoverBuilder::stats`](crate::RemoverBuilder::stats).
+    pub fn report(&self) -> Option<&RemovalReport> {
+        self.report.as_deref()
+    }
+
+    /// The error reported by the OS, without the path and operation.
+    pub fn into_io_error(self) -> io::Error {
+        self.error
+    }
+}
+
+impl Display for RemoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to {} {}: {}",
+            self.operation,
+            self.path().display(),
+            self.error
+        )?;
+        match self.rest.as_ref().map_or(0, |rest| rest.failures.len()) {
+            0 => Ok(()),
+            1 => write!(f, " (and 1 more failure)"),
+            n => write!(f, " (and {n} more failures)"),
+        }
+    }
+}
+
+impl Error for RemoveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<RemoveError> for io::Error {
+    fn from(e: RemoveError) -> Self {
+        io::Error::new(e.error.kind(), e)
+    }
+}
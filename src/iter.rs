@@ -0,0 +1,70 @@
+//! Removing a tree one entry at a time.
+
+use std::{fmt, iter::FusedIterator, path::Path};
+
+use crate::{
+    _impl::{steps::Steps, OsIo},
+    EntryMetadata, EntryType, RemoveError,
+};
+
+/// Removes a tree one entry at a time, as it is iterated; made by
+/// [`Remover::remove_iter`](crate::Remover::remove_iter).
+///
+/// Each call to [`Iterator::next`] removes one entry, so the caller sets the
+/// pace: it can interleave other work, or stop by dropping the iterator,
+/// which leaves the rest of the tree in place. Directories are opened and
+/// emptied through handles, as by [`Remover::remove_dir_all`](crate::Remover::remove_dir_all),
+/// and removed once empty; the directory given comes last.
+///
+/// Removal is serial and entries are removed one syscall at a time, whatever
+/// the [`Remover`](crate::Remover) says: its parallel and io_uring settings,
+/// and inode ordering, don't apply. Each directory is read a few dozen names
+/// at a time, so no step does much more work than another, and the metadata
+/// of each entry is read. A failure ends iteration, except in
+/// [keep-going](crate::RemoverBuilder::keep_going) mode, where each is
+/// yielded in turn and iteration carries on.
+pub struct RemoveIter {
+    pub(crate) steps: Steps<OsIo>,
+}
+
+impl Iterator for RemoveIter {
+    type Item = Result<RemovedEntry, RemoveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let removed = self.steps.next()?;
+        Some(removed.map(|(path, metadata)| RemovedEntry { path, metadata }))
+    }
+}
+
+impl FusedIterator for RemoveIter {}
+
+impl fmt::Debug for RemoveIter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoveIter").finish_non_exhaustive()
+    }
+}
+
+/// An entry removed by a [`RemoveIter`].
+#[derive(Debug, Clone)]
+pub struct RemovedEntry {
+    path: std::path::PathBuf,
+    metadata: EntryMetadata,
+}
+
+impl RemovedEntry {
+    /// The path of the entry, starting from the path given to
+    /// [`Remover::remove_iter`](crate::Remover::remove_iter).
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The type of the entry.
+    pub fn file_type(&self) -> EntryType {
+        self.metadata.file_type
+    }
+
+    /// The metadata of the entry, read just before it was removed.
+    pub fn metadata(&self) -> &EntryMetadata {
+        &self.metadata
+    }
+}
@@ -0,0 +1,114 @@
+//! Removing from async code, on tokio's blocking pool.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::{Operation, RemovalReport, RemoveError, RemoveErrorKind, Remover, RemoverBuilder};
+
+impl Remover {
+    /// [`Remover::remove_dir_all_with_report`], run on tokio's blocking pool.
+    ///
+    /// Dropping the future stops removal at its next check, as
+    /// [`RemovalControl::cancel`](crate::RemovalControl::cancel) does,
+    /// leaving the rest of the tree in place.
+    pub async fn remove_dir_all_async<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<RemovalReport, RemoveError> {
+        self.spawn(path.as_ref(), Remover::remove_dir_all_with_report)
+            .await
+    }
+
+    /// [`Remover::remove_dir_contents`], run on tokio's blocking pool. As
+    /// with [`Remover::remove_dir_all_async`], dropping the future stops
+    /// removal.
+    pub async fn remove_dir_contents_async<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<RemovalReport, RemoveError> {
+        self.spawn(path.as_ref(), Remover::remove_dir_contents)
+            .await
+    }
+
+    /// [`Remover::ensure_empty_dir`], run on tokio's blocking pool. As with
+    /// [`Remover::remove_dir_all_async`], dropping the future stops removal.
+    pub async fn ensure_empty_dir_async<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<RemovalReport, RemoveError> {
+        self.spawn(path.as_ref(), Remover::ensure_empty_dir).await
+    }
+
+    /// Run `remove` on `path` on the blocking pool, with a clone of this
+    /// remover that stops once the future is dropped.
+    async fn spawn(
+        &self,
+        path: &Path,
+        remove: fn(&Remover, PathBuf) -> Result<RemovalReport, RemoveError>,
+    ) -> Result<RemovalReport, RemoveError> {
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let mut remover = self.clone();
+        remover.abandoned = Some(abandoned.clone());
+        let _guard = Abandon(abandoned);
+        let owned = path.to_path_buf();
+        match tokio::task::spawn_blocking(move || remove(&remover, owned)).await {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            // The runtime is shutting down
+            Err(e) => Err(RemoveError::new(
+                path.into(),
+                Operation::Spawn,
+                RemoveErrorKind::Cancelled,
+                std::io::Error::other(e),
+            )),
+        }
+    }
+}
+
+/// Stops the removal when the future waiting on it is dropped, finished or
+/// not.
+struct Abandon(Arc<AtomicBool>);
+
+impl Drop for Abandon {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// [`remove_dir_all`](crate::remove_dir_all), run on tokio's blocking pool.
+/// Dropping the future stops removal, leaving the rest of the tree in place.
+pub async fn remove_dir_all_async<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    let remover = RemoverBuilder::new().build();
+    remover
+        .remove_dir_all_async(path)
+        .await
+        .map(drop)
+        .map_err(RemoveError::into_io_error)
+}
+
+/// [`remove_dir_contents`](crate::remove_dir_contents), run on tokio's
+/// blocking pool. Dropping the future stops removal.
+pub async fn remove_dir_contents_async<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    let remover = RemoverBuilder::new().build();
+    remover
+        .remove_dir_contents_async(path)
+        .await
+        .map(drop)
+        .map_err(RemoveError::into_io_error)
+}
+
+/// [`ensure_empty_dir`](crate::ensure_empty_dir), run on tokio's blocking
+/// pool. Dropping the future stops removal.
+pub async fn ensure_empty_dir_async<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    let remover = RemoverBuilder::new().build();
+    remover
+        .ensure_empty_dir_async(path)
+        .await
+        .map(drop)
+        .map_err(RemoveError::into_io_error)
+}
@@ -0,0 +1,191 @@
+//! Planning a removal, to inspect before carrying it out.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    _impl::{self, OsIo},
+    Entry, EntryMetadata, EntryType, HookAction, RemovalReport, RemoveError, Remover,
+};
+
+/// Every entry of a tree, listed by [`Remover::plan`] to be inspected before
+/// [`DeletionPlan::execute`] removes them.
+///
+/// The tree may change between the two. Execution removes only the entries
+/// listed, and only while each is still the entry listed - of the same type
+/// and, on Unix, with the same device and inode number. Anything else is left
+/// in place, with the directories holding it, and reported as a
+/// [`ChangedEntry`].
+#[derive(Debug, Clone)]
+pub struct DeletionPlan {
+    root: PathBuf,
+    metadata: EntryMetadata,
+    entries: Vec<PlannedEntry>,
+    remover: Remover,
+}
+
+/// An entry of a [`DeletionPlan`].
+#[derive(Debug, Clone)]
+pub struct PlannedEntry {
+    relative_path: PathBuf,
+    metadata: EntryMetadata,
+}
+
+impl PlannedEntry {
+    /// The path of the entry, relative to [`DeletionPlan::root`].
+    pub fn relative_path(&self) -> &Path {
+        &self.relative_path
+    }
+
+    /// The type of the entry.
+    pub fn file_type(&self) -> EntryType {
+        self.metadata.file_type
+    }
+
+    /// The metadata of the entry, read when the plan was made.
+    pub fn metadata(&self) -> &EntryMetadata {
+        &self.metadata
+    }
+}
+
+impl DeletionPlan {
+    pub(crate) fn new(root: PathBuf, remover: &Remover) -> Result<Self, RemoveError> {
+        let (metadata, entries) = _impl::plan_path::<OsIo>(&root, remover)?;
+        let mut entries: Vec<_> = entries
+            .into_iter()
+            .map(|(relative_path, metadata)| PlannedEntry {
+                relative_path,
+                metadata,
+            })
+            .collect();
+        entries.sort_unstable_by(|a, b| a.relative_path.cmp(&b.relative_path));
+        Ok(Self {
+            root,
+            metadata,
+            entries,
+            remover: remover.clone(),
+        })
+    }
+
+    /// The directory to remove.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The metadata of the directory to remove, read when the plan was made.
+    pub fn metadata(&self) -> &EntryMetadata {
+        &self.metadata
+    }
+
+    /// The entries under the directory, sorted by path, so each directory
+    /// comes before its contents.
+    pub fn entries(&self) -> &[PlannedEntry] {
+        &self.entries
+    }
+
+    /// The space removal would free, in bytes, as counted by
+    /// [`RemovalReport::bytes_freed`].
+    pub fn bytes(&self) -> u64 {
+        let entries = self.entries.iter().map(|entry| &entry.metadata);
+        std::iter::once(&self.metadata)
+            .chain(entries)
+            .map(EntryMetadata::bytes_freed)
+            .sum()
+    }
+
+    /// Remove the entries planned, with the [`Remover`] that made the plan,
+    /// and the directory itself.
+    ///
+    /// If the directory itself has been replaced this fails with
+    /// [`RemoveErrorKind::Replaced`](crate::RemoveErrorKind::Replaced) before
+    /// removing anything. Entries added or replaced since the plan was made
+    /// are skipped as a
+    /// [`RemoverBuilder::before_remove`](crate::RemoverBuilder::before_remove)
+    /// hook returning [`HookAction::Skip`] would skip them; any such hook of
+    /// the remover is called on the rest.
+    pub fn execute(self) -> Result<ExecutedPlan, RemoveError> {
+        let planned: HashMap<_, _> = self
+            .entries
+            .into_iter()
+            .map(|entry| (entry.relative_path, entry.metadata))
+            .collect();
+        let changed = Arc::new(Mutex::new(Vec::new()));
+        let mut remover = self.remover;
+        let hook = remover.hooks.before.take();
+        remover.hooks.before = Some({
+            let changed = changed.clone();
+            Arc::new(move |entry: &Entry<'_>| {
+                let change = match planned.get(entry.relative_path()) {
+                    None => Change::Added,
+                    Some(metadata) if !metadata.is_same_entry(entry.metadata()) => Change::Replaced,
+                    Some(_) => return hook.as_ref().map_or(HookAction::Remove, |hook| hook(entry)),
+                };
+                changed.lock().unwrap().push(ChangedEntry {
+                    relative_path: entry.relative_path().to_path_buf(),
+                    change,
+                });
+                HookAction::Skip
+            })
+        });
+        let report = _impl::remove_planned::<OsIo>(&self.root, &self.metadata, &remover)?;
+        let mut changed = std::mem::take(&mut *changed.lock().unwrap());
+        changed.sort_unstable_by(|a, b| a.relative_path.cmp(&b.relative_path));
+        Ok(ExecutedPlan { report, changed })
+    }
+}
+
+/// What [`DeletionPlan::execute`] did.
+#[derive(Debug, Clone)]
+pub struct ExecutedPlan {
+    report: RemovalReport,
+    changed: Vec<ChangedEntry>,
+}
+
+impl ExecutedPlan {
+    /// What was removed. Entries that had changed count as
+    /// [skipped](RemovalReport::skipped).
+    pub fn report(&self) -> &RemovalReport {
+        &self.report
+    }
+
+    /// The entries left in place because they had changed since the plan was
+    /// made, sorted by path. The contents of a changed directory are not
+    /// looked at, and so not listed.
+    pub fn changed(&self) -> &[ChangedEntry] {
+        &self.changed
+    }
+}
+
+/// An entry left in place by [`DeletionPlan::execute`] because it had changed
+/// since the plan was made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedEntry {
+    relative_path: PathBuf,
+    change: Change,
+}
+
+impl ChangedEntry {
+    /// The path of the entry, relative to [`DeletionPlan::root`].
+    pub fn relative_path(&self) -> &Path {
+        &self.relative_path
+    }
+
+    /// How the entry had changed.
+    pub fn change(&self) -> Change {
+        self.change
+    }
+}
+
+/// How an entry had changed since a [`DeletionPlan`] was made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Change {
+    /// The entry was not in the plan.
+    Added,
+    /// The entry at the path is no longer the one planned: its type, or on
+    /// Unix its device and inode number, differ.
+    Replaced,
+}
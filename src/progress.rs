@@ -0,0 +1,112 @@
+//! Following a removal as it goes.
+
+use std::{fmt, path::Path, sync::Arc, time::Duration};
+
+/// Receives updates on the progress of a removal, registered with
+/// [`RemoverBuilder::progress`](crate::RemoverBuilder::progress).
+///
+/// Updates are throttled to one per
+/// [`RemoverBuilder::progress_interval`](crate::RemoverBuilder::progress_interval),
+/// plus a last one when removal finishes, and are made from whichever thread
+/// is removing at the time. Removal waits for [`Progress::update`] to return,
+/// so it should be quick: hand the update to a UI thread, say, rather than
+/// redraw from it.
+///
+/// Closures taking a [`ProgressUpdate`] implement it.
+pub trait Progress: Send + Sync {
+    /// Called with how far removal has got.
+    fn update(&self, update: &ProgressUpdate<'_>);
+}
+
+impl<F: Fn(&ProgressUpdate<'_>) + Send + Sync> Progress for F {
+    fn update(&self, update: &ProgressUpdate<'_>) {
+        self(update)
+    }
+}
+
+/// How far a removal has got.
+#[derive(Debug, Clone)]
+pub struct ProgressUpdate<'a> {
+    pub(crate) entries_removed: u64,
+    pub(crate) bytes_freed: u64,
+    pub(crate) current_dir: &'a Path,
+    pub(crate) elapsed: Duration,
+    pub(crate) total: Option<(u64, u64)>,
+    pub(crate) finished: bool,
+}
+
+impl ProgressUpdate<'_> {
+    /// Entries removed so far: files, directories, symlinks and special
+    /// files.
+    pub fn entries_removed(&self) -> u64 {
+        self.entries_removed
+    }
+
+    /// Space freed so far, in bytes, as counted by
+    /// [`RemovalReport::bytes_freed`](crate::RemovalReport::bytes_freed).
+    pub fn bytes_freed(&self) -> u64 {
+        self.bytes_freed
+    }
+
+    /// The directory last read. In parallel removal other directories are
+    /// being worked on too.
+    pub fn current_dir(&self) -> &Path {
+        self.current_dir
+    }
+
+    /// Time since removal started, not counting the pre-scan.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Entries found by the pre-scan, if
+    /// [`RemoverBuilder::prescan`](crate::RemoverBuilder::prescan) was asked
+    /// for and it succeeded.
+    pub fn total_entries(&self) -> Option<u64> {
+        self.total.map(|(entries, _)| entries)
+    }
+
+    /// Bytes the pre-scan found would be freed.
+    pub fn total_bytes(&self) -> Option<u64> {
+        self.total.map(|(_, bytes)| bytes)
+    }
+
+    /// The fraction of the entries found by the pre-scan removed so far,
+    /// from 0 to 1.
+    pub fn fraction(&self) -> Option<f64> {
+        let total = self.total_entries()?;
+        if total == 0 {
+            return Some(1.0);
+        }
+        Some((self.entries_removed as f64 / total as f64).min(1.0))
+    }
+
+    /// The time left, at the rate entries have been removed so far.
+    pub fn eta(&self) -> Option<Duration> {
+        let total = self.total_entries()?;
+        if self.entries_removed == 0 {
+            return None;
+        }
+        let left = total.saturating_sub(self.entries_removed);
+        Some(
+            self.elapsed
+                .mul_f64(left as f64 / self.entries_removed as f64),
+        )
+    }
+
+    /// Whether this is the last update, made when removal has finished,
+    /// whether or not it succeeded.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// A [`Progress`] shared by the clones of a remover.
+#[derive(Clone)]
+pub(crate) struct ProgressHandle(pub(crate) Arc<dyn Progress>);
+
+impl fmt::Debug for ProgressHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressHandle")
+    }
+}
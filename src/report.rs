@@ -0,0 +1,98 @@
+//! What a [`Remover`](crate::Remover) did.
+
+use std::{path::PathBuf, time::Duration};
+
+/// What a removal did: the entries it removed, the space freed, and the time
+/// it took.
+///
+/// Everything but [`RemovalReport::elapsed`], [`RemovalReport::skipped`] and
+/// [`RemovalReport::mount_points`] is only collected with
+/// [`RemoverBuilder::stats`](crate::RemoverBuilder::stats), and is zero
+/// otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RemovalReport {
+    pub(crate) files: u64,
+    pub(crate) dirs: u64,
+    pub(crate) symlinks: u64,
+    pub(crate) special_files: u64,
+    pub(crate) skipped: u64,
+    pub(crate) mount_points: Vec<PathBuf>,
+    pub(crate) bytes_freed: u64,
+    pub(crate) elapsed: Duration,
+    pub(crate) scan_time: Duration,
+    pub(crate) unlink_time: Duration,
+    pub(crate) rmdir_time: Duration,
+}
+
+impl RemovalReport {
+    /// Regular files removed.
+    pub fn files(&self) -> u64 {
+        self.files
+    }
+
+    /// Directories removed, including the one given to
+    /// [`Remover::remove_dir_all_with_report`](crate::Remover::remove_dir_all_with_report).
+    pub fn dirs(&self) -> u64 {
+        self.dirs
+    }
+
+    /// Symlinks removed; on Windows, junctions and other reparse points too.
+    pub fn symlinks(&self) -> u64 {
+        self.symlinks
+    }
+
+    /// FIFOs, sockets and devices removed.
+    pub fn special_files(&self) -> u64 {
+        self.special_files
+    }
+
+    /// Entries left in place by the
+    /// [`RemoverBuilder::before_remove`](crate::RemoverBuilder::before_remove)
+    /// hook, or mount points left by
+    /// [`RemoverBuilder::one_file_system`](crate::RemoverBuilder::one_file_system).
+    /// This is counted without
+    /// [`RemoverBuilder::stats`](crate::RemoverBuilder::stats).
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// The mount points left in place, with the directories holding them, in
+    /// one-file-system mode with
+    /// [`OnMountPoint::Skip`](crate::OnMountPoint::Skip), sorted. Empty on
+    /// Windows.
+    pub fn mount_points(&self) -> &[PathBuf] {
+        &self.mount_points
+    }
+
+    /// Space freed, in bytes: the blocks allocated to the entries removed
+    /// (`st_blocks` on Unix), which for sparse or compressed files is less
+    /// than their length. Files with hard links outside the tree are still
+    /// allocated, and are not counted.
+    pub fn bytes_freed(&self) -> u64 {
+        self.bytes_freed
+    }
+
+    /// Time from start to finish.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Time spent reading directories. In parallel removal this is summed
+    /// over every thread, so the phases can add up to more than
+    /// [`RemovalReport::elapsed`].
+    pub fn scan_time(&self) -> Duration {
+        self.scan_time
+    }
+
+    /// Time spent removing entries other than emptied directories, including
+    /// opening directories to descend into them.
+    pub fn unlink_time(&self) -> Duration {
+        self.unlink_time
+    }
+
+    /// Time spent removing emptied directories.
+    pub fn rmdir_time(&self) -> Duration {
+        self.rmdir_time
+    }
+}
@@ -0,0 +1,111 @@
+//! Deletion under a tight file descriptor limit. This lowers RLIMIT_NOFILE for
+//! the whole process, so it lives in its own test binary.
+#![cfg(not(windows))]
+
+use std::{fs, io, path::Path, sync::Mutex};
+
+use tempfile::TempDir;
+use test_log::test;
+
+/// Descriptors the removal may use beyond those already open.
+const SPARE_FDS: libc::rlim_t = 6;
+
+const DEPTH: usize = 100;
+
+/// Held by each test from start to finish: the limit applies to the whole
+/// process, so no other test may be opening descriptors while it is lowered.
+static LIMITED: Mutex<()> = Mutex::new(());
+
+/// The highest descriptor currently open.
+fn max_open_fd() -> libc::c_int {
+    (0..4096)
+        .rev()
+        .find(|&fd| unsafe { libc::fcntl(fd, libc::F_GETFD) } != -1)
+        .unwrap_or(2)
+}
+
+/// Restores the descriptor limit when dropped, even if the test panicked.
+struct Restore(libc::rlimit);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        assert_eq!(0, unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &self.0) });
+    }
+}
+
+/// Run `f` with only [`SPARE_FDS`] descriptors to spare. The caller must hold
+/// [`LIMITED`].
+fn with_few_descriptors<T>(f: impl FnOnce() -> T) -> T {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    assert_eq!(0, unsafe {
+        libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit)
+    });
+    let _restore = Restore(limit);
+    limit.rlim_cur = max_open_fd() as libc::rlim_t + 1 + SPARE_FDS;
+    assert_eq!(0, unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) });
+    f()
+}
+
+/// Build a tree [`DEPTH`] directories deep, with a file in each, and remove
+/// it with `f` while only [`SPARE_FDS`] descriptors are to spare. [`LIMITED`]
+/// is held throughout, from creating the tree to cleaning up after `f`.
+fn remove_deep_tree(f: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    // Whether or not another test panicked holding it
+    let _limited = LIMITED.lock().unwrap_or_else(|e| e.into_inner());
+    let tempdir = TempDir::new()?;
+    let root = tempdir.path().join("deep");
+    let mut path = root.clone();
+    for i in 0..DEPTH {
+        path.push("d");
+        fs::create_dir_all(&path)?;
+        fs::write(path.join(format!("f{i}")), b"x")?;
+    }
+
+    with_few_descriptors(|| f(&root))?;
+
+    assert!(!path.exists());
+    assert!(!root.exists());
+    Ok(())
+}
+
+#[test]
+fn deep_tree_with_few_descriptors() -> io::Result<()> {
+    let remover = remove_dir_all::RemoverBuilder::new().serial().build();
+    remove_deep_tree(|root| remover.remove_dir_all(root))
+}
+
+#[test]
+fn plan_deep_tree_with_few_descriptors() -> io::Result<()> {
+    let remover = remove_dir_all::RemoverBuilder::new().serial().build();
+    remove_deep_tree(|root| {
+        let plan = remover.plan(root)?;
+        assert_eq!(2 * DEPTH, plan.entries().len());
+        plan.execute()?;
+        Ok(())
+    })
+}
+
+#[test]
+fn prescan_deep_tree_with_few_descriptors() -> io::Result<()> {
+    let totals = std::sync::Arc::new(Mutex::new(Vec::new()));
+    let seen = totals.clone();
+    let remover = remove_dir_all::RemoverBuilder::new()
+        .serial()
+        .prescan()
+        .progress(move |update: &remove_dir_all::ProgressUpdate| {
+            seen.lock().unwrap().push(update.total_entries())
+        })
+        .build();
+    remove_deep_tree(|root| remover.remove_dir_all(root))?;
+
+    let totals = totals.lock().unwrap();
+    assert!(!totals.is_empty());
+    // Every entry, and the directory itself
+    assert!(totals
+        .iter()
+        .all(|&total| total == Some(2 * DEPTH as u64 + 1)));
+    Ok(())
+}
@@ -230,3 +230,1100 @@ fn removes_symlinks() {
 }
 
 // TODO: Should probably test readonly hard links...
+
+/// Deeper than a recursive traversal could manage on `SMALL_STACK`.
+const DEEP: usize = 2_000;
+
+const SMALL_STACK: usize = 64 * 1024;
+
+fn open_dir(path: &Path) -> fs::File {
+    let mut options = fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(windows)]
+    std::os::windows::fs::OpenOptionsExt::custom_flags(
+        &mut options,
+        windows_sys::Win32::Storage::FileSystem::FILE_FLAG_BACKUP_SEMANTICS,
+    );
+    options.open(path).unwrap()
+}
+
+/// Make a chain of `depth` directories below `path`, each holding a file.
+/// The full path is far longer than PATH_MAX, so it is built with handles.
+fn make_deep_tree(path: &Path, depth: usize) {
+    fs::create_dir(path).unwrap();
+    let mut dir = open_dir(path);
+    let mut opts = fs_at::OpenOptions::default();
+    opts.read(true).write(fs_at::OpenOptionsWriteMode::Write);
+    for _ in 0..depth {
+        opts.create_new(true).open_at(&dir, "file").unwrap();
+        dir = opts.mkdir_at(&dir, "d").unwrap();
+    }
+}
+
+fn removes_deep_tree(remover: remove_dir_all::Remover) {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("deep");
+    make_deep_tree(&path, DEEP);
+    let result = std::thread::Builder::new()
+        .stack_size(SMALL_STACK)
+        .spawn({
+            let path = path.clone();
+            move || remover.remove_dir_all_with_report(path)
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    result.unwrap();
+    assert_not_found!(&path);
+}
+
+#[test]
+fn removes_deep_tree_serial() {
+    removes_deep_tree(remove_dir_all::RemoverBuilder::new().serial().build());
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn removes_deep_tree_parallel() {
+    removes_deep_tree(remove_dir_all::RemoverBuilder::new().parallel().build());
+}
+
+/// Make a tree wider than the directories worked on at once, with one
+/// directory holding more entries than are scanned in a batch.
+fn make_branchy_tree(path: &Path) {
+    for i in 0..100 {
+        for j in 0..3 {
+            let dir = path.join(format!("{i}")).join(format!("{j}"));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("file"), b"x").unwrap();
+        }
+    }
+    let wide = path.join("wide");
+    fs::create_dir(&wide).unwrap();
+    for i in 0..2_500 {
+        fs::write(wide.join(format!("{i}")), b"x").unwrap();
+    }
+}
+
+/// The entries in a tree made by [`make_branchy_tree`].
+const BRANCHY_TREE_ENTRIES: u64 = 100 + 100 * 3 * 2 + 1 + 2_500;
+
+fn removes_branchy_tree(remover: remove_dir_all::Remover) {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("branchy");
+    make_branchy_tree(&path);
+    // The 1.x signature, returning the bare error
+    let removed: std::io::Result<()> = remover.remove_dir_all(&path);
+    removed.unwrap();
+    assert_not_found!(&path);
+}
+
+#[test]
+fn removes_branchy_tree_serial() {
+    removes_branchy_tree(remove_dir_all::RemoverBuilder::new().serial().build());
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn removes_branchy_tree_parallel() {
+    removes_branchy_tree(remove_dir_all::RemoverBuilder::new().parallel().build());
+}
+
+#[test]
+fn removes_branchy_tree_inode_order_serial() {
+    removes_branchy_tree(
+        remove_dir_all::RemoverBuilder::new()
+            .serial()
+            .inode_order(100)
+            .build(),
+    );
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn removes_branchy_tree_inode_order_parallel() {
+    removes_branchy_tree(
+        remove_dir_all::RemoverBuilder::new()
+            .parallel()
+            .inode_order(100)
+            .build(),
+    );
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+#[test]
+fn removes_branchy_tree_io_uring_serial() {
+    removes_branchy_tree(
+        remove_dir_all::RemoverBuilder::new()
+            .serial()
+            .io_uring()
+            .build(),
+    );
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring", feature = "parallel"))]
+#[test]
+fn removes_branchy_tree_io_uring_parallel() {
+    removes_branchy_tree(
+        remove_dir_all::RemoverBuilder::new()
+            .parallel()
+            .io_uring()
+            .build(),
+    );
+}
+
+// Remover entry points
+
+fn serial_remover() -> remove_dir_all::Remover {
+    remove_dir_all::RemoverBuilder::new().serial().build()
+}
+
+#[test]
+fn remover_ensure_empty_dir() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("newdir");
+    serial_remover().ensure_empty_dir(&path).unwrap();
+    assert_empty(&path);
+    fs::write(path.join("child"), b"aa").unwrap();
+    serial_remover().ensure_empty_dir(&path).unwrap();
+    assert_empty(&path);
+}
+
+#[test]
+fn remover_remove_dir_contents() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    fs::create_dir_all(path.join("subdir")).unwrap();
+    fs::write(path.join("subdir").join("child"), b"aa").unwrap();
+    serial_remover().remove_dir_contents(&path).unwrap();
+    assert_empty(&path);
+}
+
+#[test]
+fn remover_remove_dir_contents_of_handle() {
+    use remove_dir_all::RemoveDir;
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    fs::create_dir_all(path.join("subdir")).unwrap();
+    fs::write(path.join("subdir").join("child"), b"aa").unwrap();
+    open_dir(&path)
+        .remove_dir_contents_with(&serial_remover(), Some(&path))
+        .unwrap();
+    assert_empty(&path);
+
+    // Failures are the bare OS error, as from `remove_dir_contents`
+    fs::create_dir(path.join("subdir")).unwrap();
+    let remover = remove_dir_all::RemoverBuilder::new()
+        .before_remove(|_| remove_dir_all::HookAction::Abort)
+        .build();
+    let err = open_dir(&path)
+        .remove_dir_contents_with(&remover, Some(&path))
+        .unwrap_err();
+    let inner = err.get_ref().unwrap();
+    assert!(!inner.is::<remove_dir_all::RemoveError>(), "{err:?}");
+}
+
+#[cfg(not(windows))]
+#[test]
+fn removes_dir_at() {
+    use remove_dir_all::RemoveDirAt;
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let report = open_dir(tempdir.path())
+        .remove_dir_all_at_with(
+            &remove_dir_all::RemoverBuilder::new().stats().build(),
+            "dir",
+            None,
+        )
+        .unwrap();
+    assert_not_found!(&path);
+    assert_eq!(BRANCHY_TREE_ENTRIES + 1, report.files() + report.dirs());
+}
+
+#[cfg(not(windows))]
+#[test]
+fn remove_dir_at_checks_identity() {
+    use std::os::unix::fs::MetadataExt;
+
+    use remove_dir_all::{EntryId, RemoveDirAt, RemoveErrorKind};
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    fs::create_dir_all(path.join("sub")).unwrap();
+    let metadata = fs::metadata(&path).unwrap();
+    let id = EntryId::new(metadata.dev(), metadata.ino());
+    let parent = open_dir(tempdir.path());
+    let wrong = EntryId::new(metadata.dev(), metadata.ino() + 1);
+    let err = parent
+        .remove_dir_all_at_with(&serial_remover(), "dir", Some(wrong))
+        .unwrap_err();
+    assert_eq!(RemoveErrorKind::Replaced, err.kind());
+    assert_eq!(Path::new("dir"), err.path());
+    assert_exists(&path.join("sub"));
+    parent.remove_dir_all_at("dir", Some(id)).unwrap();
+    assert_not_found!(&path);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn remove_dir_at_takes_one_component() {
+    use remove_dir_all::RemoveDirAt;
+
+    let tempdir = TempDir::new().unwrap();
+    fs::create_dir_all(tempdir.path().join("dir/sub")).unwrap();
+    let parent = open_dir(tempdir.path());
+    for name in ["", ".", "..", "dir/sub", "dir/", "/dir"] {
+        let err = parent.remove_dir_all_at(name, None).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidInput, err.kind(), "{name:?}");
+    }
+    assert_exists(&tempdir.path().join("dir/sub"));
+}
+
+#[cfg(not(windows))]
+#[test]
+fn remove_dir_at_refuses_symlink() {
+    use remove_dir_all::RemoveDirAt;
+
+    let tempdir = TempDir::new().unwrap();
+    fs::create_dir_all(tempdir.path().join("target/sub")).unwrap();
+    std::os::unix::fs::symlink("target", tempdir.path().join("link")).unwrap();
+    open_dir(tempdir.path())
+        .remove_dir_all_at("link", None)
+        .unwrap_err();
+    assert_exists(&tempdir.path().join("target/sub"));
+}
+
+#[cfg(not(windows))]
+#[test]
+fn removes_beneath_base() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("a/dir");
+    make_branchy_tree(&path);
+    let remover = remove_dir_all::RemoverBuilder::new()
+        .beneath(open_dir(tempdir.path()))
+        .build();
+    remover.remove_dir_contents("a/dir").unwrap();
+    assert_empty(&path);
+    fs::write(path.join("file"), b"").unwrap();
+    remover.ensure_empty_dir("a/dir").unwrap();
+    assert_empty(&path);
+    remover.ensure_empty_dir("a/new").unwrap();
+    assert_empty(&tempdir.path().join("a/new"));
+    remover.remove_dir_all_with_report("a/dir").unwrap();
+    assert_not_found!(&path);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn refuses_paths_leaving_base() {
+    use remove_dir_all::RemoveErrorKind;
+
+    let tempdir = TempDir::new().unwrap();
+    let base = tempdir.path().join("base");
+    fs::create_dir_all(base.join("dir")).unwrap();
+    fs::create_dir_all(tempdir.path().join("outside/dir")).unwrap();
+    std::os::unix::fs::symlink("../outside", base.join("link")).unwrap();
+    let remover = remove_dir_all::RemoverBuilder::new()
+        .beneath(open_dir(&base))
+        .build();
+    let outside = tempdir.path().join("outside");
+    for path in [
+        Path::new("../outside/dir"),
+        Path::new("dir/../../outside/dir"),
+        &outside.join("dir"),
+        Path::new("link/dir"),
+        Path::new(""),
+    ] {
+        let err = remover.remove_dir_all_with_report(path).unwrap_err();
+        assert_eq!(RemoveErrorKind::NotBeneath, err.kind(), "{path:?}: {err}");
+        if path != Path::new("") {
+            let err = remover.remove_dir_contents(path.join("dir")).unwrap_err();
+            assert_eq!(RemoveErrorKind::NotBeneath, err.kind(), "{path:?}: {err}");
+        }
+    }
+    assert_exists(&outside.join("dir"));
+    assert_exists(&base.join("dir"));
+}
+
+#[cfg(not(windows))]
+#[test]
+fn strict_paths_refuse_symlinks_along_the_way() {
+    use remove_dir_all::RemoveErrorKind;
+
+    let tempdir = TempDir::new().unwrap();
+    fs::create_dir_all(tempdir.path().join("elsewhere/build/sub")).unwrap();
+    let link = tempdir.path().join("link");
+    std::os::unix::fs::symlink("elsewhere", &link).unwrap();
+    let remover = remove_dir_all::RemoverBuilder::new().strict_paths().build();
+    let err = remover
+        .remove_dir_all_with_report(link.join("build"))
+        .unwrap_err();
+    assert_eq!(RemoveErrorKind::SymlinkInPath, err.kind(), "{err}");
+    let err = remover.remove_dir_contents(link.join("build")).unwrap_err();
+    assert_eq!(RemoveErrorKind::SymlinkInPath, err.kind(), "{err}");
+    let err = remover.ensure_empty_dir(link.join("build")).unwrap_err();
+    assert_eq!(RemoveErrorKind::SymlinkInPath, err.kind(), "{err}");
+    assert_exists(&tempdir.path().join("elsewhere/build/sub"));
+
+    // `..` after a name is refused, as the name might be a symlink
+    let err = remover
+        .remove_dir_all_with_report(link.join("../elsewhere/build"))
+        .unwrap_err();
+    assert_eq!(RemoveErrorKind::Other, err.kind(), "{err}");
+    assert_exists(&tempdir.path().join("elsewhere/build/sub"));
+
+    // Without symlinks, paths are removed as usual
+    let path = tempdir.path().join("elsewhere/./build");
+    remover.remove_dir_all_with_report(&path).unwrap();
+    assert_not_found!(&tempdir.path().join("elsewhere/build"));
+}
+
+#[cfg(not(windows))]
+#[test]
+fn one_file_system_removes_trees_without_mounts() {
+    use remove_dir_all::OnMountPoint;
+
+    for on_mount_point in [OnMountPoint::Fail, OnMountPoint::Skip] {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("root");
+        make_branchy_tree(&path);
+        let report = remove_dir_all::RemoverBuilder::new()
+            .one_file_system(on_mount_point)
+            .build()
+            .remove_dir_all_with_report(&path)
+            .unwrap();
+        assert_eq!(0, report.skipped());
+        assert!(report.mount_points().is_empty());
+        assert_not_found!(&path);
+    }
+}
+
+#[test]
+fn remover_error_missing_path() {
+    use remove_dir_all::{Operation, RemoveError, RemoveErrorKind};
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("missing");
+    let err = serial_remover()
+        .remove_dir_all_with_report(&path)
+        .unwrap_err();
+    assert_eq!(RemoveErrorKind::NotFound, err.kind());
+    assert_eq!(Operation::Open, err.operation());
+    assert_eq!(path, err.path());
+    assert_eq!(std::io::ErrorKind::NotFound, err.io_error().kind());
+
+    // Converting keeps the kind, and the error for those who look for it
+    let err = std::io::Error::from(err);
+    assert_eq!(std::io::ErrorKind::NotFound, err.kind());
+    let inner = err.get_ref().unwrap().downcast_ref::<RemoveError>();
+    assert_eq!(path, inner.unwrap().path());
+
+    // Relative paths are reported as given
+    let path = Path::new("missing").join("relative");
+    let err = serial_remover()
+        .remove_dir_all_with_report(&path)
+        .unwrap_err();
+    assert_eq!(path, err.path());
+}
+
+#[test]
+fn remover_error_is_file() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("file");
+    fs::write(&path, b"aa").unwrap();
+    let err = serial_remover().remove_dir_contents(&path).unwrap_err();
+    assert_eq!(
+        remove_dir_all::RemoveErrorKind::NotADirectory,
+        err.kind(),
+        "{err}"
+    );
+    assert_eq!(path, err.path());
+    assert_exists(&path);
+}
+
+/// Remove a tree of three files, one of them also linked from outside the
+/// tree, three directories, a symlink and a FIFO, checking the report.
+#[cfg(unix)]
+fn reports_what_was_removed(
+    builder: remove_dir_all::RemoverBuilder,
+) -> remove_dir_all::RemovalReport {
+    use std::os::unix::fs::{symlink, MetadataExt};
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    fs::create_dir_all(path.join("d/e")).unwrap();
+    for name in ["f", "d/f", "d/e/f"] {
+        fs::write(path.join(name), [0; 8192]).unwrap();
+    }
+    // Still allocated afterwards, through the link outside the tree
+    fs::hard_link(path.join("d/e/f"), tempdir.path().join("f")).unwrap();
+    // Freed once both are gone, which are read in the same scan
+    fs::write(path.join("h1"), [0; 8192]).unwrap();
+    fs::hard_link(path.join("h1"), path.join("h2")).unwrap();
+    symlink("f", path.join("link")).unwrap();
+    nix::unistd::mkfifo(&path.join("fifo"), nix::sys::stat::Mode::S_IRWXU).unwrap();
+    let freed: u64 = [".", "d", "d/e", "f", "d/f", "h1", "link", "fifo"]
+        .iter()
+        .map(|name| fs::symlink_metadata(path.join(name)).unwrap().blocks() * 512)
+        .sum();
+
+    let report = builder
+        .stats()
+        .build()
+        .remove_dir_all_with_report(&path)
+        .unwrap();
+    assert_not_found!(&path);
+    assert_eq!(5, report.files());
+    assert_eq!(3, report.dirs());
+    assert_eq!(1, report.symlinks());
+    assert_eq!(1, report.special_files());
+    assert_eq!(freed, report.bytes_freed());
+    assert!(report.unlink_time() > std::time::Duration::ZERO);
+    report
+}
+
+#[cfg(unix)]
+#[test]
+fn reports_what_was_removed_serial() {
+    reports_what_was_removed(remove_dir_all::RemoverBuilder::new().serial());
+}
+
+#[cfg(all(unix, feature = "parallel"))]
+#[test]
+fn reports_what_was_removed_parallel() {
+    reports_what_was_removed(remove_dir_all::RemoverBuilder::new().parallel());
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+#[test]
+fn reports_what_was_removed_io_uring() {
+    let uring = reports_what_was_removed(remove_dir_all::RemoverBuilder::new().serial().io_uring());
+    let serial = reports_what_was_removed(remove_dir_all::RemoverBuilder::new().serial());
+    let counts = |report: &remove_dir_all::RemovalReport| {
+        (
+            report.files(),
+            report.dirs(),
+            report.symlinks(),
+            report.special_files(),
+            report.bytes_freed(),
+        )
+    };
+    assert_eq!(counts(&serial), counts(&uring));
+}
+
+/// Remove a tree with progress, returning the updates made: the entries
+/// removed, the total found by any pre-scan, and whether finished.
+fn removes_with_progress(builder: remove_dir_all::RemoverBuilder) -> Vec<(u64, Option<u64>, bool)> {
+    use std::sync::{Arc, Mutex};
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let updates = Arc::new(Mutex::new(Vec::new()));
+    let progress = {
+        let updates = updates.clone();
+        let path = path.clone();
+        move |update: &remove_dir_all::ProgressUpdate<'_>| {
+            assert!(update.current_dir().starts_with(&path), "{update:?}");
+            updates.lock().unwrap().push((
+                update.entries_removed(),
+                update.total_entries(),
+                update.is_finished(),
+            ));
+        }
+    };
+    builder
+        .progress(progress)
+        .progress_interval(std::time::Duration::ZERO)
+        .build()
+        .remove_dir_all_with_report(&path)
+        .unwrap();
+    assert_not_found!(&path);
+    let updates = updates.lock().unwrap().clone();
+    updates
+}
+
+#[test]
+fn progress_is_measured_against_the_prescan() {
+    let builder = remove_dir_all::RemoverBuilder::new().serial().prescan();
+    let updates = removes_with_progress(builder);
+    let (removed, total, finished) = *updates.last().unwrap();
+    assert!(finished);
+    assert_eq!(Some(removed), total);
+    // Every entry, and the directory itself
+    assert_eq!(BRANCHY_TREE_ENTRIES + 1, removed);
+    assert!(updates.len() > 2, "{updates:?}");
+    assert!(updates.windows(2).all(|w| w[0].0 <= w[1].0 && !w[0].2));
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn progress_is_reported_in_parallel() {
+    let builder = remove_dir_all::RemoverBuilder::new().parallel();
+    let updates = removes_with_progress(builder);
+    assert_eq!(
+        Some(&(BRANCHY_TREE_ENTRIES + 1, None, true)),
+        updates.last()
+    );
+}
+
+#[test]
+fn reports_only_the_time_without_stats() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    fs::create_dir_all(path.join("subdir")).unwrap();
+    fs::write(path.join("subdir").join("child"), b"aa").unwrap();
+    let report = serial_remover().remove_dir_all_with_report(&path).unwrap();
+    assert_not_found!(&path);
+    assert_eq!(
+        (0, 0, 0),
+        (report.files(), report.dirs(), report.bytes_freed())
+    );
+    assert_eq!(std::time::Duration::ZERO, report.scan_time());
+}
+
+/// Remove a branchy tree with one file in it skipped by the before-remove
+/// hook.
+fn skips_what_the_hook_vetoes(builder: remove_dir_all::RemoverBuilder) {
+    use remove_dir_all::HookAction;
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let report = builder
+        .before_remove(|entry| {
+            assert!(entry.relative_path().is_relative(), "{entry:?}");
+            if entry.relative_path() == Path::new("7/1/file") {
+                HookAction::Skip
+            } else {
+                HookAction::Remove
+            }
+        })
+        .build()
+        .remove_dir_all_with_report(&path)
+        .unwrap();
+    assert_eq!(1, report.skipped());
+    assert_exists(&path.join("7/1/file"));
+    assert_not_found!(&path.join("7/0"));
+    assert_not_found!(&path.join("wide"));
+    let names = |path: &Path| {
+        fs::read_dir(path)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&path), ["7"]);
+    assert_eq!(names(&path.join("7")), ["1"]);
+}
+
+#[test]
+fn skips_what_the_hook_vetoes_serial() {
+    skips_what_the_hook_vetoes(remove_dir_all::RemoverBuilder::new().serial());
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn skips_what_the_hook_vetoes_parallel() {
+    skips_what_the_hook_vetoes(remove_dir_all::RemoverBuilder::new().parallel());
+}
+
+#[test]
+fn after_remove_sees_every_entry() {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+    };
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let removed = Arc::new(Mutex::new(Vec::new()));
+    let hook = {
+        let removed = removed.clone();
+        move |entry: &remove_dir_all::Entry<'_>| {
+            assert!(entry.metadata().links() > 0, "{entry:?}");
+            let path = entry.relative_path().to_path_buf();
+            removed.lock().unwrap().push((path, entry.file_type()));
+        }
+    };
+    remove_dir_all::RemoverBuilder::new()
+        .serial()
+        .after_remove(hook)
+        .build()
+        .remove_dir_all_with_report(&path)
+        .unwrap();
+    assert_not_found!(&path);
+    let removed = removed.lock().unwrap();
+    assert_eq!(BRANCHY_TREE_ENTRIES as usize, removed.len());
+    let unique: HashSet<_> = removed.iter().map(|(path, _)| path).collect();
+    assert_eq!(removed.len(), unique.len());
+    // Directories are reported after their contents
+    let wide = removed
+        .iter()
+        .position(|entry| *entry == ("wide".into(), remove_dir_all::EntryType::Dir))
+        .unwrap();
+    let before = removed[..wide].iter();
+    assert_eq!(
+        2_500,
+        before.filter(|(path, _)| path.starts_with("wide")).count()
+    );
+}
+
+#[test]
+fn hook_aborts_removal() {
+    use remove_dir_all::{HookAction, RemoveErrorKind};
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let err = remove_dir_all::RemoverBuilder::new()
+        .serial()
+        .keep_going()
+        .before_remove(|entry| match entry.relative_path().to_str() {
+            Some("wide") => HookAction::Abort,
+            _ => HookAction::Remove,
+        })
+        .build()
+        .remove_dir_all_with_report(&path)
+        .unwrap_err();
+    assert_eq!(RemoveErrorKind::Aborted, err.kind());
+    assert_eq!(path.join("wide"), err.path());
+    assert_exists(&path.join("wide/0"));
+}
+
+/// Cancel removal of a branchy tree from the after-remove hook, once 100
+/// entries have gone.
+fn cancels(builder: remove_dir_all::RemoverBuilder) {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let control = remove_dir_all::RemovalControl::new();
+    let hook = {
+        let control = control.clone();
+        let removed = AtomicU64::new(0);
+        move |_: &remove_dir_all::Entry<'_>| {
+            if removed.fetch_add(1, Ordering::Relaxed) == 99 {
+                control.cancel();
+            }
+        }
+    };
+    let err = builder
+        .stats()
+        .control(control.clone())
+        .after_remove(hook)
+        .build()
+        .remove_dir_all_with_report(&path)
+        .unwrap_err();
+    assert!(control.is_cancelled());
+    assert_eq!(remove_dir_all::RemoveErrorKind::Cancelled, err.kind());
+    let report = err.report().unwrap();
+    let removed = report.files() + report.dirs();
+    // Jobs already past their check finish in parallel removal
+    assert!((100..200).contains(&removed), "{report:?}");
+    assert_exists(&path.join("wide"));
+}
+
+#[test]
+fn cancels_serial() {
+    cancels(remove_dir_all::RemoverBuilder::new().serial());
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn cancels_parallel() {
+    cancels(remove_dir_all::RemoverBuilder::new().parallel());
+}
+
+#[test]
+fn pauses_until_resumed() {
+    use std::time::Duration;
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let control = remove_dir_all::RemovalControl::new();
+    control.pause();
+    let remover = remove_dir_all::RemoverBuilder::new()
+        .control(control.clone())
+        .build();
+    let removal = {
+        let path = path.clone();
+        std::thread::spawn(move || remover.remove_dir_all_with_report(path))
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(control.is_paused());
+    assert_eq!(101, fs::read_dir(&path).unwrap().count());
+    control.resume();
+    removal.join().unwrap().unwrap();
+    assert_not_found!(&path);
+}
+
+#[test]
+fn stops_at_the_deadline() {
+    use std::time::{Duration, Instant};
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    // Paused, so the deadline passes while waiting
+    let control = remove_dir_all::RemovalControl::new();
+    control.pause();
+    let err = remove_dir_all::RemoverBuilder::new()
+        .serial()
+        .control(control)
+        .deadline(Instant::now() + Duration::from_millis(50))
+        .build()
+        .remove_dir_all_with_report(&path)
+        .unwrap_err();
+    assert_eq!(remove_dir_all::RemoveErrorKind::TimedOut, err.kind());
+    assert_eq!(std::io::ErrorKind::TimedOut, err.io_error().kind());
+    assert_eq!(101, fs::read_dir(&path).unwrap().count());
+}
+
+#[test]
+fn removes_one_entry_per_step() {
+    use std::collections::HashSet;
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let remover = remove_dir_all::RemoverBuilder::new().build();
+    let mut removed = Vec::new();
+    for entry in remover.remove_iter(&path) {
+        let entry = entry.unwrap();
+        assert_not_found!(entry.path());
+        removed.push(entry);
+    }
+    assert_not_found!(&path);
+    assert_eq!(BRANCHY_TREE_ENTRIES + 1, removed.len() as u64);
+    let unique: HashSet<_> = removed.iter().map(|entry| entry.path()).collect();
+    assert_eq!(removed.len(), unique.len());
+    let root = removed.last().unwrap();
+    assert_eq!(path, root.path());
+    assert_eq!(remove_dir_all::EntryType::Dir, root.file_type());
+}
+
+#[test]
+fn stops_removing_when_dropped() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let remover = remove_dir_all::RemoverBuilder::new().build();
+    let mut iter = remover.remove_iter(&path);
+    let removed: Vec<_> = iter.by_ref().take(10).map(Result::unwrap).collect();
+    drop(iter);
+    for entry in &removed {
+        assert_not_found!(entry.path());
+    }
+    let left = walk(&path);
+    assert_eq!(BRANCHY_TREE_ENTRIES - 10, left);
+}
+
+/// The number of entries under `path`.
+fn walk(path: &Path) -> u64 {
+    fs::read_dir(path)
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            if entry.file_type().unwrap().is_dir() {
+                1 + walk(&entry.path())
+            } else {
+                1
+            }
+        })
+        .sum()
+}
+
+#[test]
+fn remove_iter_yields_open_failure() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("missing");
+    let remover = remove_dir_all::RemoverBuilder::new().build();
+    let mut iter = remover.remove_iter(&path);
+    let err = iter.next().unwrap().unwrap_err();
+    assert_eq!(remove_dir_all::RemoveErrorKind::NotFound, err.kind());
+    assert!(iter.next().is_none());
+}
+
+#[cfg(feature = "tokio")]
+#[test(tokio::test)]
+async fn removes_async() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let report = remove_dir_all::RemoverBuilder::new()
+        .stats()
+        .build()
+        .remove_dir_all_async(&path)
+        .await
+        .unwrap();
+    assert_not_found!(&path);
+    assert_eq!(BRANCHY_TREE_ENTRIES + 1, report.files() + report.dirs());
+
+    make_branchy_tree(&path);
+    remove_dir_all::remove_dir_contents_async(&path)
+        .await
+        .unwrap();
+    assert_empty(&path);
+    remove_dir_all::remove_dir_all_async(&path).await.unwrap();
+    assert_not_found!(&path);
+}
+
+#[cfg(feature = "tokio")]
+#[test(tokio::test)]
+async fn dropping_the_future_stops_removal() {
+    use std::time::Duration;
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let control = remove_dir_all::RemovalControl::new();
+    control.pause();
+    let remover = remove_dir_all::RemoverBuilder::new()
+        .control(control.clone())
+        .build();
+    let removal = remover.remove_dir_all_async(&path);
+    tokio::time::timeout(Duration::from_millis(50), removal)
+        .await
+        .unwrap_err();
+    // Give the abandoned removal time to stop, then let it carry on if it
+    // hasn't
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    control.resume();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(101, fs::read_dir(&path).unwrap().count());
+}
+
+#[test]
+fn executes_plan() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    make_branchy_tree(&path);
+    let plan = remove_dir_all::RemoverBuilder::new()
+        .build()
+        .plan(&path)
+        .unwrap();
+    assert_eq!(BRANCHY_TREE_ENTRIES, plan.entries().len() as u64);
+    assert_eq!(BRANCHY_TREE_ENTRIES, walk(&path));
+    let executed = plan.execute().unwrap();
+    assert_not_found!(&path);
+    assert!(executed.changed().is_empty());
+    assert_eq!(0, executed.report().skipped());
+}
+
+/// Replace the directory at `path` with an empty one, keeping the old one so
+/// that its inode isn't reused.
+#[cfg(not(windows))]
+fn replace_dir(path: &Path) {
+    fs::rename(path, path.with_extension("old")).unwrap();
+    fs::create_dir(path).unwrap();
+}
+
+// Windows removes the root by path
+#[cfg(not(windows))]
+#[test]
+fn keeps_root_replaced_once_emptied() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    fs::create_dir(&path).unwrap();
+    fs::write(path.join("file"), b"").unwrap();
+    let hook = {
+        let path = path.clone();
+        move |_: &remove_dir_all::Entry<'_>| replace_dir(&path)
+    };
+    let err = remove_dir_all::RemoverBuilder::new()
+        .after_remove(hook)
+        .build()
+        .remove_dir_all_with_report(&path)
+        .unwrap_err();
+    assert_eq!(remove_dir_all::RemoveErrorKind::Replaced, err.kind());
+    assert_eq!(remove_dir_all::Operation::Rmdir, err.operation());
+    assert_exists(&path);
+    assert_exists(&path.with_extension("old"));
+}
+
+#[cfg(not(windows))]
+#[test]
+fn remove_iter_keeps_root_replaced_once_emptied() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    fs::create_dir(&path).unwrap();
+    fs::write(path.join("file"), b"").unwrap();
+    let remover = remove_dir_all::RemoverBuilder::new().build();
+    let mut iter = remover.remove_iter(&path);
+    assert_eq!(path.join("file"), iter.next().unwrap().unwrap().path());
+    replace_dir(&path);
+    let err = iter.next().unwrap().unwrap_err();
+    assert_eq!(remove_dir_all::RemoveErrorKind::Replaced, err.kind());
+    assert!(iter.next().is_none());
+    assert_exists(&path);
+}
+
+// Windows compares only the types of entries
+#[cfg(not(windows))]
+#[test]
+fn plan_keeps_what_changed() {
+    use remove_dir_all::Change;
+
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    fs::create_dir_all(path.join("a/b")).unwrap();
+    fs::write(path.join("a/b/replaced"), b"old").unwrap();
+    fs::write(path.join("a/kept"), b"").unwrap();
+    fs::create_dir(path.join("c")).unwrap();
+    let plan = remove_dir_all::RemoverBuilder::new()
+        .build()
+        .plan(&path)
+        .unwrap();
+    assert_eq!(5, plan.entries().len());
+    // Write the new file before removing the old, so it can't reuse the inode
+    fs::write(path.join("a/b/new"), b"new").unwrap();
+    fs::rename(path.join("a/b/new"), path.join("a/b/replaced")).unwrap();
+    fs::write(path.join("c/added"), b"").unwrap();
+    let executed = plan.execute().unwrap();
+    let changed: Vec<_> = executed
+        .changed()
+        .iter()
+        .map(|entry| (entry.relative_path().to_path_buf(), entry.change()))
+        .collect();
+    assert_eq!(
+        vec![
+            (Path::new("a/b/replaced").to_path_buf(), Change::Replaced),
+            (Path::new("c/added").to_path_buf(), Change::Added),
+        ],
+        changed
+    );
+    assert_eq!(b"new", &fs::read(path.join("a/b/replaced")).unwrap()[..]);
+    assert_exists(&path.join("c/added"));
+    assert_not_found!(&path.join("a/kept"));
+}
+
+// Windows compares only the types of entries
+#[cfg(not(windows))]
+#[test]
+fn plan_refuses_replaced_root() {
+    let tempdir = TempDir::new().unwrap();
+    let path = tempdir.path().join("dir");
+    fs::create_dir_all(path.join("sub")).unwrap();
+    let plan = remove_dir_all::RemoverBuilder::new()
+        .build()
+        .plan(&path)
+        .unwrap();
+    // Keep the old directory, so its inode isn't reused
+    fs::rename(&path, tempdir.path().join("old")).unwrap();
+    fs::create_dir_all(path.join("sub")).unwrap();
+    let err = plan.execute().unwrap_err();
+    assert_eq!(remove_dir_all::RemoveErrorKind::Replaced, err.kind());
+    assert_exists(&path.join("sub"));
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn removes_branchy_tree_supplied_pool() {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(3)
+        .build()
+        .unwrap();
+    removes_branchy_tree(
+        remove_dir_all::RemoverBuilder::new()
+            .thread_pool(std::sync::Arc::new(pool))
+            .max_in_flight(2)
+            .build(),
+    );
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn removes_branchy_tree_dedicated_threads() {
+    removes_branchy_tree(
+        remove_dir_all::RemoverBuilder::new()
+            .threads(4)
+            .max_in_flight(3)
+            .build(),
+    );
+}
+
+#[cfg(feature = "threads")]
+#[test]
+fn removes_deep_tree_std_threads() {
+    removes_deep_tree(remove_dir_all::RemoverBuilder::new().std_threads(3).build());
+}
+
+#[cfg(feature = "threads")]
+#[test]
+fn removes_branchy_tree_std_threads() {
+    removes_branchy_tree(
+        remove_dir_all::RemoverBuilder::new()
+            .std_threads(4)
+            .max_in_flight(3)
+            .build(),
+    );
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn removes_branchy_tree_adaptive() {
+    removes_branchy_tree(remove_dir_all::RemoverBuilder::new().adaptive().build());
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn adaptive_starts_workers_as_the_limit_rises() {
+    use std::{
+        collections::HashSet,
+        sync::{mpsc, Arc, Mutex, Once},
+        time::Duration,
+    };
+
+    let pool = Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap(),
+    );
+    let threads = Arc::new(Mutex::new(HashSet::new()));
+    let first = Once::new();
+    let remover = remove_dir_all::RemoverBuilder::new()
+        .thread_pool(pool.clone())
+        .adaptive()
+        .before_remove({
+            let threads = threads.clone();
+            move |_| {
+                // With one job in flight to begin with, one worker is started,
+                // leaving the rest of the pool free for other work
+                first.call_once(|| {
+                    let (sender, receiver) = mpsc::channel();
+                    pool.spawn(move || sender.send(()).unwrap());
+                    receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+                });
+                // Slow operations then raise the limit, and more are started
+                std::thread::sleep(Duration::from_micros(200));
+                threads
+                    .lock()
+                    .unwrap()
+                    .insert(rayon::current_thread_index());
+                remove_dir_all::HookAction::Remove
+            }
+        })
+        .build();
+    removes_branchy_tree(remover);
+    let threads = threads.lock().unwrap();
+    assert!(threads.len() > 1, "{threads:?}");
+    assert!(threads.iter().all(Option::is_some), "{threads:?}");
+}
+
+#[cfg(feature = "threads")]
+#[test]
+fn removes_branchy_tree_adaptive_std_threads() {
+    removes_branchy_tree(
+        remove_dir_all::RemoverBuilder::new()
+            .std_threads(4)
+            .adaptive()
+            .build(),
+    );
+}